@@ -0,0 +1,74 @@
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct Address(u32);
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct Expr<T>(T);
+
+#[derive(Debug)]
+#[allow(dead_code)]
+enum Term {
+	Var(u32),
+	Const(u32),
+	Op(&'static str),
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+enum Insn {
+	Goto(Address),
+	Label(Address),
+	Expr(Expr<Expr<Vec<Term>>>, Address),
+}
+
+fn code() -> Vec<Insn> {
+	(0..100)
+		.flat_map(|i| {
+			[
+				Insn::Goto(Address(30016 + i)),
+				Insn::Label(Address(29990 + i)),
+				Insn::Expr(
+					Expr(Expr(vec![Term::Var(i), Term::Const(0), Term::Op("Ne")])),
+					Address(30016),
+				),
+			]
+		})
+		.collect()
+}
+
+fn find(c: &mut Criterion) {
+	c.bench_function("find", |b| {
+		b.iter(|| compact_debug::find_uncached().unwrap())
+	});
+}
+
+fn toggle(c: &mut Criterion) {
+	c.bench_function("enable and disable", |b| {
+		b.iter(|| unsafe {
+			compact_debug::enable(true);
+			compact_debug::enable(false);
+		})
+	});
+}
+
+fn format(c: &mut Criterion) {
+	let code = code();
+	let mut group = c.benchmark_group("format");
+	// In case the `auto` feature enabled it
+	unsafe { compact_debug::enable(false) };
+	group.bench_function("disabled", |b| {
+		b.iter(|| format!("{:#?}", black_box(&code)))
+	});
+	unsafe { compact_debug::enable(true) };
+	group.bench_function("enabled", |b| b.iter(|| format!("{:#?}", black_box(&code))));
+	unsafe { compact_debug::enable(false) };
+	group.finish();
+}
+
+criterion_group!(benches, find, toggle, format);
+criterion_main!(benches);
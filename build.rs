@@ -0,0 +1,140 @@
+//! Records which compiler the crate is built with, since the patch depends on exactly how it
+//! compiled `std`, and checks whether the functions that derived `Debug` implementations call
+//! still need a feature gate. It also picks out the entries in `offsets.txt` for the compiler, so
+//! that the functions do not have to be scanned.
+
+use std::path::Path;
+use std::process::Command;
+
+/// The commit dates of the oldest and newest nightlies that the patch is known to work with.
+const TESTED: (&str, &str) = ("2024-09-01", "2026-05-19");
+
+fn main() {
+	println!("cargo:rerun-if-changed=build.rs");
+	println!("cargo:rerun-if-changed=offsets.txt");
+	println!("cargo:rerun-if-env-changed=RUSTC");
+	println!("cargo:rustc-check-cfg=cfg(compact_debug_fmt_helpers)");
+	println!("cargo:rustc-check-cfg=cfg(compact_debug_fmt_helpers_feature)");
+	if std::env::var_os("CARGO_FEATURE_PATCH").is_none() {
+		return;
+	}
+
+	let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+	probe_fmt_helpers(&rustc);
+	let verbose = Command::new(&rustc)
+		.args(["--version", "--verbose"])
+		.output()
+		.ok()
+		.and_then(|output| String::from_utf8(output.stdout).ok())
+		.unwrap_or_default();
+	let version = verbose.lines().next().unwrap_or("unknown rustc");
+	let field = |name: &str| {
+		verbose
+			.lines()
+			.find_map(|line| line.strip_prefix(name)?.strip_prefix(": "))
+			.unwrap_or("unknown")
+	};
+	let date = field("commit-date");
+	let commit = field("commit-hash");
+	let target = std::env::var("TARGET").unwrap();
+	write_offsets(commit, &target);
+
+	println!("cargo:rustc-env=COMPACT_DEBUG_RUSTC_VERSION={version}");
+	println!("cargo:rustc-env=COMPACT_DEBUG_RUSTC_DATE={date}");
+	println!("cargo:rustc-env=COMPACT_DEBUG_RUSTC_COMMIT={commit}");
+	println!("cargo:rustc-env=COMPACT_DEBUG_TARGET={target}");
+	println!("cargo:rustc-env=COMPACT_DEBUG_TESTED_FROM={}", TESTED.0);
+	println!("cargo:rustc-env=COMPACT_DEBUG_TESTED_TO={}", TESTED.1);
+	// Dates are compared as strings, which works since they are all `YYYY-MM-DD`
+	if !(TESTED.0..=TESTED.1).contains(&date) {
+		println!(
+			"cargo:warning=compact-debug has only been tested with nightlies from {} to {}, not \
+			 {version}; the patch may fail or misbehave",
+			TESTED.0, TESTED.1,
+		);
+	}
+}
+
+/// Sets `compact_debug_fmt_helpers` if `Formatter::debug_tuple_field1_finish` and friends exist,
+/// and `compact_debug_fmt_helpers_feature` if they are still behind `fmt_helpers_for_derive`.
+///
+/// This compiles a tiny crate using them, first without the feature and then with it, rather than
+/// comparing version numbers, since nobody knows in advance which nightly will change it.
+fn probe_fmt_helpers(rustc: &str) {
+	const PROBE: &str =
+		"pub fn probe() -> usize { core::fmt::Formatter::debug_tuple_field1_finish as usize }\n";
+	if compiles(rustc, PROBE) {
+		println!("cargo:rustc-cfg=compact_debug_fmt_helpers");
+	} else if compiles(
+		rustc,
+		&format!("#![feature(fmt_helpers_for_derive)]\n{PROBE}"),
+	) {
+		println!("cargo:rustc-cfg=compact_debug_fmt_helpers");
+		println!("cargo:rustc-cfg=compact_debug_fmt_helpers_feature");
+	} else {
+		println!(
+			"cargo:warning=compact-debug could not find Formatter::debug_tuple_field1_finish, so \
+			 the patch cannot affect derived Debug implementations directly, and `hook` is \
+			 unavailable"
+		);
+	}
+}
+
+fn compiles(rustc: &str, source: &str) -> bool {
+	let out_dir = std::env::var("OUT_DIR").unwrap();
+	let path = Path::new(&out_dir).join("probe.rs");
+	std::fs::write(&path, source).unwrap();
+	let mut command = Command::new(rustc);
+	command
+		.args([
+			"--crate-type=lib",
+			"--crate-name=probe",
+			"--edition=2021",
+			"--emit=metadata",
+		])
+		.arg("--out-dir")
+		.arg(&out_dir)
+		.arg(&path);
+	if let Ok(target) = std::env::var("TARGET") {
+		command.args(["--target", &target]);
+	}
+	command.output().is_ok_and(|output| output.status.success())
+}
+
+/// Writes the entries in `offsets.txt` for `commit` and `target` to `offsets.rs` in `OUT_DIR`, as
+/// a list of functions and the offsets of the sites in them.
+///
+/// Each line in `offsets.txt` holds the commit hash of the compiler, the target, the function, and
+/// the offsets in hex, separated by spaces. Blank lines and lines starting with `#` are ignored.
+fn write_offsets(commit: &str, target: &str) {
+	let table = std::fs::read_to_string("offsets.txt").unwrap_or_default();
+	let mut out = String::from("const KNOWN_OFFSETS: &[(&str, &[usize])] = &[\n");
+	for (i, line) in table.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+		let mut words = line.split_whitespace();
+		let (Some(c), Some(t), Some(function)) = (words.next(), words.next(), words.next()) else {
+			panic!(
+				"offsets.txt:{}: expected a commit, target and function",
+				i + 1
+			);
+		};
+		if c != commit || t != target {
+			continue;
+		}
+		let offsets = words
+			.map(|word| {
+				let hex = word.strip_prefix("0x").unwrap_or(word);
+				usize::from_str_radix(hex, 16)
+					.unwrap_or_else(|_| panic!("offsets.txt:{}: invalid offset {word}", i + 1))
+					.to_string()
+			})
+			.collect::<Vec<_>>();
+		out += &format!("\t({function:?}, &[{}]),\n", offsets.join(", "));
+	}
+	out += "];\n";
+	let path = Path::new(&std::env::var("OUT_DIR").unwrap()).join("offsets.rs");
+	std::fs::write(path, out).unwrap();
+}
@@ -0,0 +1,73 @@
+//! Prints the example from the crate docs with and without the patch, followed by where it was
+//! written, to check that it works with the toolchain at hand:
+//!
+//! ```text
+//! cargo run --example demo
+//! ```
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct Address(u32);
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct Expr<T>(T);
+
+#[derive(Debug)]
+#[allow(dead_code)]
+enum Term {
+	Var(u32),
+	Const(u32),
+	Op(Op),
+}
+
+#[derive(Debug)]
+enum Op {
+	Ne,
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+enum Insn {
+	Goto(Address),
+	Label(Address),
+	Expr(Expr<Expr<Vec<Term>>>, Address),
+}
+
+fn main() {
+	let code = [
+		Insn::Goto(Address(30016)),
+		Insn::Label(Address(29990)),
+		Insn::Expr(
+			Expr(Expr(vec![Term::Var(0), Term::Const(0), Term::Op(Op::Ne)])),
+			Address(30016),
+		),
+	];
+	println!("{}", compact_debug::std_build_info());
+	if !compact_debug::compatible() {
+		println!("(this compiler has not been tested with the patch)");
+	}
+
+	// As it is with the `auto` feature
+	if compact_debug::is_enabled() {
+		unsafe { compact_debug::disable() };
+	}
+	println!("\n--- before ---\n{code:#?}");
+	if let Err(e) = unsafe { compact_debug::try_enable(true) } {
+		println!("\ncould not enable the patch: {e}");
+		return;
+	}
+	println!("\n--- after ---\n{code:#?}");
+
+	println!("\n--- patch sites ---");
+	for site in compact_debug::patch_report() {
+		println!(
+			"{} at {:#x}+{:#x}, originally {:#x}",
+			site.function, site.address, site.offset, site.original,
+		);
+	}
+	println!("\n--- sites per function ---");
+	for (function, found) in compact_debug::discovery_stats().per_function {
+		println!("{function}: {found}");
+	}
+}
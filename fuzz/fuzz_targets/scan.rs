@@ -0,0 +1,14 @@
+//! Feeds arbitrary bytes to the x86 scan, which should neither panic, nor run forever, nor find a
+//! site outside of them. Run with `cargo fuzz run scan` on x86_64, adding `--features decode` for
+//! the scan that decodes each instruction.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (bool, u8, u8, &[u8])| {
+	let (exact, offset, mask, code) = input;
+	if let Ok(sites) = compact_debug::scan_code(code, exact, offset.into(), mask) {
+		assert!(sites.iter().all(|&site| site < code.len()), "{sites:?}");
+	}
+});
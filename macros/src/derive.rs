@@ -0,0 +1,264 @@
+//! `#[derive(CompactDebug)]`, parsed by hand to avoid depending on `syn`.
+//!
+//! Only as much is parsed as is needed to write the impl: the name, the generic parameters, the
+//! where clause, and the names or number of fields of the struct or of each variant.
+
+use proc_macro::{Delimiter, Spacing, Span, TokenStream, TokenTree};
+use std::iter::Peekable;
+
+type Tokens = Peekable<proc_macro::token_stream::IntoIter>;
+type Error = (Span, &'static str);
+
+enum Fields {
+	Unit,
+	Tuple(usize),
+	Named(Vec<String>),
+}
+
+pub fn derive(input: TokenStream) -> Result<String, Error> {
+	let mut tokens = input.into_iter().peekable();
+	skip_attrs(&mut tokens);
+	let (kind, span) = match tokens.next() {
+		Some(TokenTree::Ident(kind)) => (kind.to_string(), kind.span()),
+		_ => return Err((Span::call_site(), "expected a struct or enum")),
+	};
+	let Some(TokenTree::Ident(name)) = tokens.next() else {
+		return Err((span, "expected a name"));
+	};
+	let name = name.to_string();
+	let generics = if is_punct(tokens.peek(), '<') {
+		tokens.next();
+		let mut depth = 1;
+		let mut generics = Vec::new();
+		for tt in tokens.by_ref() {
+			match &tt {
+				TokenTree::Punct(p) if p.as_char() == '<' => depth += 1,
+				TokenTree::Punct(p) if p.as_char() == '>' => depth -= 1,
+				_ => {}
+			}
+			if depth == 0 {
+				break;
+			}
+			generics.push(tt);
+		}
+		generics
+	} else {
+		Vec::new()
+	};
+
+	let mut where_clause = Vec::new();
+	let mut take_where = |tokens: &mut Tokens| {
+		if matches!(tokens.peek(), Some(TokenTree::Ident(i)) if i.to_string() == "where") {
+			tokens.next();
+			while let Some(tt) = tokens.next_if(|tt| !is_body(tt)) {
+				where_clause.push(tt);
+			}
+		}
+	};
+	take_where(&mut tokens);
+	let body = match (kind.as_str(), tokens.next()) {
+		("struct", Some(TokenTree::Group(g))) if g.delimiter() == Delimiter::Parenthesis => {
+			take_where(&mut tokens);
+			struct_body(&name, Fields::Tuple(split(g.stream()).len()))
+		}
+		("struct", Some(TokenTree::Group(g))) if g.delimiter() == Delimiter::Brace => {
+			struct_body(&name, Fields::Named(field_names(g.stream())?))
+		}
+		("struct", _) => struct_body(&name, Fields::Unit),
+		("enum", Some(TokenTree::Group(g))) if g.delimiter() == Delimiter::Brace => {
+			enum_body(g.stream())?
+		}
+		_ => {
+			return Err((
+				span,
+				"CompactDebug can only be derived for structs and enums",
+			))
+		}
+	};
+
+	let mut params = Vec::new();
+	let mut args = Vec::new();
+	let mut bounds = String::new();
+	if !where_clause.is_empty() {
+		bounds = TokenStream::from_iter(where_clause).to_string();
+		if !bounds.trim_end().ends_with(',') {
+			bounds.push(',');
+		}
+	}
+	for param in split(TokenStream::from_iter(generics)) {
+		// Defaults are only allowed on the type
+		let end = param
+			.iter()
+			.position(|tt| is_punct(Some(tt), '='))
+			.unwrap_or(param.len());
+		let param = &param[..end];
+		params.push(TokenStream::from_iter(param.iter().cloned()).to_string());
+		match param {
+			[TokenTree::Punct(p), TokenTree::Ident(lifetime), ..] if p.as_char() == '\'' => {
+				args.push(format!("'{lifetime}"))
+			}
+			[TokenTree::Ident(c), TokenTree::Ident(name), ..] if c.to_string() == "const" => {
+				args.push(name.to_string())
+			}
+			[TokenTree::Ident(name), ..] => {
+				args.push(name.to_string());
+				bounds += &format!("{name}: ::core::fmt::Debug,");
+			}
+			_ => return Err((span, "could not parse the generic parameters")),
+		}
+	}
+
+	Ok(format!(
+		"#[automatically_derived] \
+		impl<{params}> ::core::fmt::Debug for {name}<{args}> where {bounds} {{ \
+			fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {{ {body} }} \
+		}}",
+		params = params.join(", "),
+		args = args.join(", "),
+	))
+}
+
+fn struct_body(name: &str, fields: Fields) -> String {
+	let (pattern, body) = fields_body(&unraw(name), &fields);
+	format!("let Self{pattern} = *self; {body}")
+}
+
+fn enum_body(variants: TokenStream) -> Result<String, Error> {
+	let mut arms = String::new();
+	for variant in split(variants) {
+		let mut tokens = TokenStream::from_iter(variant).into_iter().peekable();
+		skip_attrs(&mut tokens);
+		let Some(TokenTree::Ident(name)) = tokens.next() else {
+			return Err((Span::call_site(), "expected a variant name"));
+		};
+		let fields = match tokens.next() {
+			Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => {
+				Fields::Tuple(split(g.stream()).len())
+			}
+			Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => {
+				Fields::Named(field_names(g.stream())?)
+			}
+			_ => Fields::Unit,
+		};
+		let (pattern, body) = fields_body(&unraw(&name.to_string()), &fields);
+		arms += &format!("Self::{name}{pattern} => {{ {body} }}");
+	}
+	Ok(format!("match *self {{ {arms} }}"))
+}
+
+/// Returns the pattern binding each field to `__self_N`, and the code that writes them.
+///
+/// Tuples are written the way that `DebugTuple` writes them with the patch enabled, while the
+/// rest is written the same way as with `#[derive(Debug)]`.
+fn fields_body(name: &str, fields: &Fields) -> (String, String) {
+	let unit = |pattern: &str| (pattern.to_owned(), format!("f.write_str({name:?})"));
+	match fields {
+		Fields::Unit => unit(""),
+		Fields::Tuple(0) => unit("()"),
+		Fields::Named(names) if names.is_empty() => unit(" {}"),
+		Fields::Tuple(n) => {
+			let binds = (0..*n)
+				.map(|i| format!("ref __self_{i}"))
+				.collect::<Vec<_>>();
+			let mut body = format!("f.write_str({:?})?;", format!("{name}("));
+			for i in 0..*n {
+				if i != 0 {
+					body += "f.write_str(\", \")?;";
+				}
+				body += &format!("::core::fmt::Debug::fmt(__self_{i}, f)?;");
+			}
+			body += "f.write_str(\")\")";
+			(format!("({})", binds.join(", ")), body)
+		}
+		Fields::Named(names) => {
+			let binds = names
+				.iter()
+				.enumerate()
+				.map(|(i, field)| format!("{field}: ref __self_{i}"))
+				.collect::<Vec<_>>();
+			let mut body = format!("f.debug_struct({name:?})");
+			for (i, field) in names.iter().enumerate() {
+				body += &format!(".field({:?}, &__self_{i})", unraw(field));
+			}
+			body += ".finish()";
+			(format!(" {{ {} }}", binds.join(", ")), body)
+		}
+	}
+}
+
+/// Returns the names of the fields in the body of a struct or struct variant.
+fn field_names(fields: TokenStream) -> Result<Vec<String>, Error> {
+	split(fields)
+		.into_iter()
+		.map(|field| {
+			let mut tokens = TokenStream::from_iter(field).into_iter().peekable();
+			skip_attrs(&mut tokens);
+			match tokens.next() {
+				Some(TokenTree::Ident(name)) => Ok(name.to_string()),
+				_ => Err((Span::call_site(), "expected a field name")),
+			}
+		})
+		.collect()
+}
+
+/// Skips attributes and visibility.
+fn skip_attrs(tokens: &mut Tokens) {
+	loop {
+		match tokens.peek() {
+			Some(TokenTree::Punct(p)) if p.as_char() == '#' => {
+				tokens.next();
+				tokens.next();
+			}
+			Some(TokenTree::Ident(i)) if i.to_string() == "pub" => {
+				tokens.next();
+				tokens.next_if(
+					|tt| matches!(tt, TokenTree::Group(g) if g.delimiter() == Delimiter::Parenthesis),
+				);
+			}
+			_ => return,
+		}
+	}
+}
+
+/// Splits a list at the commas that are not inside brackets, including angle brackets, dropping
+/// the empty item after a trailing comma.
+fn split(stream: TokenStream) -> Vec<Vec<TokenTree>> {
+	let mut items = vec![Vec::new()];
+	let mut depth = 0usize;
+	let mut arrow = false;
+	for tt in stream {
+		if let TokenTree::Punct(p) = &tt {
+			match p.as_char() {
+				',' if depth == 0 => {
+					items.push(Vec::new());
+					continue;
+				}
+				'<' => depth += 1,
+				'>' if !arrow => depth = depth.saturating_sub(1),
+				_ => {}
+			}
+			arrow = p.as_char() == '-' && p.spacing() == Spacing::Joint;
+		} else {
+			arrow = false;
+		}
+		items.last_mut().unwrap().push(tt);
+	}
+	if items.last().is_some_and(Vec::is_empty) {
+		items.pop();
+	}
+	items
+}
+
+fn is_punct(tt: Option<&TokenTree>, c: char) -> bool {
+	matches!(tt, Some(TokenTree::Punct(p)) if p.as_char() == c)
+}
+
+/// Whether `tt` ends a where clause: the body of a struct or enum, or the `;` after a tuple struct.
+fn is_body(tt: &TokenTree) -> bool {
+	matches!(tt, TokenTree::Group(g) if g.delimiter() == Delimiter::Brace)
+		|| is_punct(Some(tt), ';')
+}
+
+fn unraw(name: &str) -> String {
+	name.strip_prefix("r#").unwrap_or(name).to_owned()
+}
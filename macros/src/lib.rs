@@ -0,0 +1,85 @@
+//! Macros for `compact-debug`. Use them through that crate rather than directly.
+
+use proc_macro::{Delimiter, Group, Ident, Punct, Spacing, Span, TokenStream, TokenTree};
+
+mod derive;
+
+/// Like `#[test]`, but with the patch enabled for the duration of the test, through
+/// `compact_debug::scoped`. It is put back the way it was even if the test fails.
+///
+/// Since the patch is shared by the whole process, it stays enabled until every test using this
+/// is done, and other tests that run at the same time are affected as well.
+///
+/// Panic messages are formatted before unwinding, while the patch is still enabled, so values in
+/// failed assertions are printed compactly too. `assert_eq!` already prints them with `{:?}`, but
+/// this matters for messages that use `{:#?}`, such as those of `pretty_assertions`.
+#[proc_macro_attribute]
+pub fn test(attr: TokenStream, item: TokenStream) -> TokenStream {
+	if let Some(tt) = attr.into_iter().next() {
+		return error(
+			tt.span(),
+			"#[compact_debug::test] does not take any arguments",
+		);
+	}
+	let mut item = item.into_iter().collect::<Vec<_>>();
+	let Some(TokenTree::Group(body)) = item.pop() else {
+		return error(
+			Span::call_site(),
+			"#[compact_debug::test] must be applied to a function",
+		);
+	};
+	if body.delimiter() != Delimiter::Brace {
+		return error(
+			body.span(),
+			"#[compact_debug::test] must be applied to a function",
+		);
+	}
+	let guard = "let _guard = unsafe { ::compact_debug::scoped() };"
+		.parse::<TokenStream>()
+		.unwrap();
+	let mut new_body = Group::new(
+		Delimiter::Brace,
+		guard.into_iter().chain(body.stream()).collect(),
+	);
+	new_body.set_span(body.span());
+
+	let mut out = "#[::core::prelude::v1::test]"
+		.parse::<TokenStream>()
+		.unwrap();
+	out.extend(item);
+	out.extend([TokenTree::Group(new_body)]);
+	out
+}
+
+/// Implements `Debug` such that tuple structs and tuple variants are always printed on a single
+/// line, the same way as `DebugTuple` with the patch enabled, without needing the patch.
+///
+/// Structs, struct variants and unit variants are printed the same way as with `#[derive(Debug)]`.
+/// The fields are printed with their own `Debug` implementations, so they are only printed on a
+/// single line if they are tuples that derive this too, or if the patch is enabled.
+#[proc_macro_derive(CompactDebug)]
+pub fn derive_compact_debug(item: TokenStream) -> TokenStream {
+	match derive::derive(item) {
+		Ok(code) => code.parse().unwrap(),
+		Err((span, message)) => error(span, message),
+	}
+}
+
+/// Expands to `compile_error!(message)` at `span`.
+fn error(span: Span, message: &str) -> TokenStream {
+	let tokens = [
+		TokenTree::Ident(Ident::new("compile_error", span)),
+		TokenTree::Punct(Punct::new('!', Spacing::Alone)),
+		TokenTree::Group(Group::new(
+			Delimiter::Parenthesis,
+			TokenTree::Literal(proc_macro::Literal::string(message)).into(),
+		)),
+	];
+	tokens
+		.into_iter()
+		.map(|mut tt| {
+			tt.set_span(span);
+			tt
+		})
+		.collect()
+}
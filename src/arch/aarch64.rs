@@ -0,0 +1,175 @@
+use crate::patch::{readable_end, symbol_size, Bound, Flag, SearchError};
+
+/// Number of bytes written at each patch site.
+pub const LEN: usize = 4;
+
+const NOP: u32 = 0xD503201F;
+const RET: u32 = 0xD65F03C0;
+
+/// A `tbz`/`tbnz` instruction that branches on the alternate flag.
+///
+/// A `tbnz` is replaced with a `nop` and a `tbz` with an unconditional `b`, so that the branch
+/// always goes where it would if the flag were clear.
+#[derive(Debug, Clone, Copy)]
+pub struct Pos {
+	addr: usize,
+	orig: u32,
+}
+
+impl Pos {
+	/// The original instruction at the site.
+	pub fn orig(&self) -> u32 {
+		self.orig
+	}
+
+	pub fn ptr(&self) -> *const u8 {
+		self.addr as *const u8
+	}
+
+	fn value(&self, on: bool) -> u32 {
+		if on {
+			patched(self.orig)
+		} else {
+			self.orig
+		}
+	}
+
+	/// Whether the site already holds what `write(on)` would write.
+	pub unsafe fn is_written(&self, on: bool) -> bool {
+		unsafe { *(self.addr as *const u32) == self.value(on) }
+	}
+
+	pub unsafe fn write(&self, on: bool) {
+		unsafe { (self.addr as *mut u32).write(self.value(on)) }
+	}
+}
+
+fn patched(insn: u32) -> u32 {
+	if insn & (1 << 24) != 0 {
+		NOP
+	} else {
+		let imm14 = ((insn >> 5) & 0x3FFF) as i32;
+		let imm26 = (imm14 << 18 >> 18) as u32 & 0x03FF_FFFF;
+		0x14000000 | imm26
+	}
+}
+
+/// If `insn` loads from `[xN, #imm]` into `rt`, returns which bit of the loaded register holds
+/// the flag, if the flag is within the loaded range.
+fn load_bit(insn: u32, rt: u32, flag: Flag) -> Option<u32> {
+	if insn & 0x1F != rt {
+		return None;
+	}
+	let size = match insn & 0xFFC00000 {
+		0x39400000 => 1, // ldrb
+		0x79400000 => 2, // ldrh
+		0xB9400000 => 4, // ldr w
+		0xF9400000 => 8, // ldr x
+		_ => return None,
+	};
+	let start = ((insn >> 10) & 0xFFF) as usize * size;
+	(start..start + size)
+		.contains(&flag.offset)
+		.then(|| (flag.offset - start) as u32 * 8 + flag.mask.trailing_zeros())
+}
+
+/// If `ptr` holds a `tbz`/`tbnz` on the flag, which is loaded at most eight instructions before
+/// it, returns it as a site.
+unsafe fn site_at(start: *const u32, ptr: *const u32, flag: Flag) -> Option<Pos> {
+	unsafe {
+		let insn = *ptr;
+		if insn & 0x7E000000 != 0x36000000 {
+			return None;
+		}
+		let rt = insn & 0x1F;
+		let bit = (insn >> 31) << 5 | (insn >> 19) & 0x1F;
+		let back = (1..=ptr.offset_from(start).min(8) as usize)
+			.find(|&back| *ptr.sub(back) & 0x1F == rt)?;
+		(load_bit(*ptr.sub(back), rt, flag) == Some(bit)).then_some(Pos {
+			addr: ptr as usize,
+			orig: insn,
+		})
+	}
+}
+
+/// Scans a function for a load of the flag followed by a `tbz`/`tbnz` on the right bit, stopping
+/// at the end of the function if its size is known, and otherwise at a `ret` that ends on a
+/// 16-byte boundary.
+pub unsafe fn do_find(
+	out: &mut Vec<Pos>,
+	name: &'static str,
+	ptr: *const u8,
+	flag: Flag,
+) -> Result<*const u8, SearchError> {
+	unsafe {
+		let n = out.len();
+		let start = ptr as *const u32;
+		let mut ptr = start;
+		let bound = Bound::new(name, ptr as *const u8);
+		while !bound.reached(name, ptr)? {
+			let insn = *ptr;
+			out.extend(site_at(start, ptr, flag));
+			ptr = ptr.add(1);
+			if !bound.exact && insn == RET && (ptr as usize & 0xF) == 0 {
+				break;
+			}
+		}
+		if out.len() == n {
+			return Err(SearchError::PatternNotFound { function: name });
+		}
+		Ok(start as *const u8)
+	}
+}
+
+/// Like [`do_find`], but only checks that the branch is at each of `offsets` from the start of
+/// the function, returning `None` if any of them does not hold one, or is not an instruction of
+/// the function at all.
+pub unsafe fn find_known(
+	out: &mut Vec<Pos>,
+	_name: &'static str,
+	ptr: *const u8,
+	flag: Flag,
+	offsets: &[usize],
+) -> Option<*const u8> {
+	unsafe {
+		if offsets.iter().any(|&offset| offset % 4 != 0) {
+			return None;
+		}
+		// An offset past the end of the function, or of what can be read, has to be wrong anyway
+		let needed = ptr as usize + offsets.iter().max().map_or(0, |&offset| offset + 4);
+		let end = match symbol_size(ptr as usize) {
+			Some(size) => ptr as usize + size,
+			None => readable_end(ptr as usize, needed),
+		};
+		if needed > end {
+			return None;
+		}
+		let start = ptr as *const u32;
+		let found = offsets
+			.iter()
+			.map(|&offset| site_at(start, start.byte_add(offset), flag))
+			.collect::<Option<Vec<_>>>()?;
+		out.extend(found);
+		Some(ptr)
+	}
+}
+
+/// Cleans the written bytes to the point of unification and invalidates them in the instruction
+/// cache, so that the new instructions are fetched.
+///
+/// Cache lines are at least four bytes, so stepping by four covers every line at some cost of
+/// redundancy, without needing to read `CTR_EL0`.
+pub unsafe fn flush_icache(ptr: *const u8, len: usize) {
+	unsafe {
+		let start = ptr as usize & !3;
+		let end = ptr as usize + len;
+		for addr in (start..end).step_by(4) {
+			std::arch::asm!("dc cvau, {}", in(reg) addr);
+		}
+		std::arch::asm!("dsb ish");
+		for addr in (start..end).step_by(4) {
+			std::arch::asm!("ic ivau, {}", in(reg) addr);
+		}
+		std::arch::asm!("dsb ish", "isb");
+	}
+}
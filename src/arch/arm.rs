@@ -0,0 +1,384 @@
+use crate::patch::{Bound, Flag, SearchError};
+
+/// Number of bytes written at each patch site.
+///
+/// Narrow branches are only two bytes, but protecting a few extra bytes is harmless.
+pub const LEN: usize = 4;
+
+const NOP: u32 = 0xBF00;
+const NOP_W: u32 = 0x8000_F3AF;
+const BX_LR: u32 = 0x4770;
+
+/// How many instructions may separate the steps of the pattern.
+const WINDOW: usize = 4;
+
+/// A conditional branch on the alternate flag, after a `tst` or `lsls` that puts it in the flags.
+///
+/// A branch taken when the flag is set is replaced with a `nop`, and one taken when it is clear
+/// with an unconditional `b`, so that the branch always goes where it would if the flag were
+/// clear. For a `b` made conditional by an `it`, the site is the `b` or the `it` respectively.
+#[derive(Debug, Clone, Copy)]
+pub struct Pos {
+	addr: usize,
+	orig: u32,
+	patched: u32,
+	len: usize,
+}
+
+impl Pos {
+	/// The original instruction at the site.
+	pub fn orig(&self) -> u32 {
+		self.orig
+	}
+
+	pub fn ptr(&self) -> *const u8 {
+		self.addr as *const u8
+	}
+
+	/// Whether the site already holds what `write(on)` would write.
+	pub unsafe fn is_written(&self, on: bool) -> bool {
+		let insn = if on { self.patched } else { self.orig };
+		let ptr = self.addr as *const u16;
+		unsafe { *ptr == insn as u16 && (self.len == 2 || *ptr.add(1) == (insn >> 16) as u16) }
+	}
+
+	pub unsafe fn write(&self, on: bool) {
+		let insn = if on { self.patched } else { self.orig };
+		let ptr = self.addr as *mut u16;
+		unsafe {
+			ptr.write(insn as u16);
+			if self.len == 4 {
+				ptr.add(1).write((insn >> 16) as u16);
+			}
+		}
+	}
+}
+
+/// Reads the Thumb instruction that `code` starts with, with the first halfword in the low bits,
+/// and its length in bytes, unless `code` ends before it does.
+fn read(code: &[u16]) -> Option<(u32, usize)> {
+	let lo = *code.first()? as u32;
+	if matches!(lo >> 11, 0b11101..=0b11111) {
+		Some((lo | (*code.get(1)? as u32) << 16, 4))
+	} else {
+		Some((lo, 2))
+	}
+}
+
+fn sext(value: u32, bits: u32) -> i32 {
+	(value << (32 - bits)) as i32 >> (32 - bits)
+}
+
+/// If `insn` loads from `[rN, #imm]`, returns the destination register and which bit of it holds
+/// the flag, if the flag is within the loaded range.
+fn load_bit(insn: u32, len: usize, flag: Flag) -> Option<(u32, u32)> {
+	let (rt, start, size) = if len == 2 {
+		let (rt, imm5) = (insn & 7, (insn >> 6) & 0x1F);
+		match insn & 0xF800 {
+			0x7800 => (rt, imm5 as usize, 1),     // ldrb
+			0x8800 => (rt, imm5 as usize * 2, 2), // ldrh
+			0x6800 => (rt, imm5 as usize * 4, 4), // ldr
+			_ => return None,
+		}
+	} else {
+		let (rt, imm12) = (insn >> 28, (insn >> 16) & 0xFFF);
+		match insn & 0xFFF0 {
+			0xF890 => (rt, imm12 as usize, 1), // ldrb.w
+			0xF8B0 => (rt, imm12 as usize, 2), // ldrh.w
+			0xF8D0 => (rt, imm12 as usize, 4), // ldr.w
+			_ => return None,
+		}
+	};
+	(start..start + size).contains(&flag.offset).then(|| {
+		(
+			rt,
+			(flag.offset - start) as u32 * 8 + flag.mask.trailing_zeros(),
+		)
+	})
+}
+
+/// Decodes a Thumb-2 modified immediate.
+fn expand_imm(imm12: u32) -> u32 {
+	let imm8 = imm12 & 0xFF;
+	match imm12 >> 8 {
+		0 => imm8,
+		1 => imm8 << 16 | imm8,
+		2 => imm8 << 24 | imm8 << 8,
+		3 => imm8 * 0x01010101,
+		_ => (0x80 | imm12 & 0x7F).rotate_right(imm12 >> 7),
+	}
+}
+
+/// If `insn` sets the flags from bit `bit` of `rn`, returns the condition codes under which a
+/// branch is taken when the bit is set and when it is clear.
+fn test_bit(insn: u32, len: usize, rn: u32, bit: u32) -> Option<(u32, u32)> {
+	const EQ: u32 = 0b0000;
+	const NE: u32 = 0b0001;
+	const MI: u32 = 0b0100;
+	const PL: u32 = 0b0101;
+	if len == 2 {
+		// lsls rd, rm, #(31 - bit)
+		(insn & 0xF800 == 0x0000 && (insn >> 3) & 7 == rn && (insn >> 6) & 0x1F == 31 - bit)
+			.then_some((MI, PL))
+	} else {
+		// tst.w rn, #(1 << bit)
+		let imm12 = (insn >> 10 & 1) << 11 | (insn >> 28 & 7) << 8 | (insn >> 16) & 0xFF;
+		(insn & 0x8F00_FBF0 == 0x0F00_F010 && insn & 0xF == rn && expand_imm(imm12) == 1 << bit)
+			.then_some((NE, EQ))
+	}
+}
+
+/// If `insn` is a conditional branch on `set` or `clear`, returns the replacement instruction.
+fn patch_branch(insn: u32, len: usize, (set, clear): (u32, u32)) -> Option<u32> {
+	if len == 2 {
+		if insn & 0xF000 != 0xD000 {
+			return None;
+		}
+		let cond = (insn >> 8) & 0xF;
+		if cond == set {
+			Some(NOP)
+		} else if cond == clear {
+			Some(0xE000 | sext(insn & 0xFF, 8) as u32 & 0x7FF)
+		} else {
+			None
+		}
+	} else {
+		if insn & 0xD000_F800 != 0x8000_F000 {
+			return None;
+		}
+		let cond = (insn >> 6) & 0xF;
+		if cond == set {
+			Some(NOP_W)
+		} else if cond == clear {
+			// Decode the offset of the conditional `b<c>.w`...
+			let s = (insn >> 10) & 1;
+			let j1 = (insn >> 29) & 1;
+			let j2 = (insn >> 27) & 1;
+			let imm6 = insn & 0x3F;
+			let imm11 = (insn >> 16) & 0x7FF;
+			let off = sext(s << 20 | j2 << 19 | j1 << 18 | imm6 << 12 | imm11 << 1, 21) as u32;
+			// ...and reencode it as an unconditional `b.w`
+			let s = (off >> 24) & 1;
+			let j1 = !((off >> 23) ^ s) & 1;
+			let j2 = !((off >> 22) ^ s) & 1;
+			let imm10 = (off >> 12) & 0x3FF;
+			let imm11 = (off >> 1) & 0x7FF;
+			Some(0xF000 | s << 10 | imm10 | (0x9000 | j1 << 13 | j2 << 11 | imm11) << 16)
+		} else {
+			None
+		}
+	}
+}
+
+/// If `insn` is an `it` that makes only `branch`, an unconditional `b`, conditional on `set` or
+/// `clear`, returns which of the two to replace and with what: the branch with a `nop` if it is
+/// taken when the flag is set, and otherwise the `it`, so that the branch is always taken.
+fn patch_it(
+	insn: u32,
+	len: usize,
+	(branch, branch_len): (u32, usize),
+	(set, clear): (u32, u32),
+) -> Option<(bool, u32)> {
+	// it<c>, with a mask for a single instruction
+	if len != 2 || insn & 0xFF0F != 0xBF08 {
+		return None;
+	}
+	let unconditional = if branch_len == 2 {
+		branch & 0xF800 == 0xE000
+	} else {
+		branch & 0xD000_F800 == 0x9000_F000
+	};
+	if !unconditional {
+		return None;
+	}
+	let cond = (insn >> 4) & 0xF;
+	if cond == set {
+		Some((true, if branch_len == 2 { NOP } else { NOP_W }))
+	} else if cond == clear {
+		Some((false, NOP))
+	} else {
+		None
+	}
+}
+
+fn is_return(insn: u32, len: usize) -> bool {
+	if len == 2 {
+		insn == BX_LR || insn & 0xFF00 == 0xBD00
+	} else {
+		insn & 0x8000_FFFF == 0x8000_E8BD
+	}
+}
+
+/// Scans a function for a load of the flag, a `tst` or `lsls` testing it, and a conditional
+/// branch on the result, which may also be an unconditional `b` made conditional by an `it`.
+///
+/// The scan stops at the end of the function if its size is known. Otherwise, since Thumb
+/// instructions are only halfword aligned, it stops at a return that is followed by either a
+/// padding `nop` or a 16-byte boundary.
+///
+/// Returns the address of the function with the Thumb bit cleared.
+pub unsafe fn do_find(
+	out: &mut Vec<Pos>,
+	name: &'static str,
+	ptr: *const u8,
+	flag: Flag,
+) -> Result<*const u8, SearchError> {
+	unsafe {
+		// Clear the Thumb bit
+		let start = (ptr as usize & !1) as *const u8;
+		let bound = Bound::new(name, start);
+		// Without an exact size, this may cover more than the function, but not more than is mapped
+		let code =
+			std::slice::from_raw_parts(start as *const u16, (bound.end - start as usize) / 2);
+		let found = scan(name, code, start as usize, bound.exact, flag)?;
+		if found.is_empty() {
+			return Err(SearchError::PatternNotFound { function: name });
+		}
+		out.extend(found);
+		Ok(start)
+	}
+}
+
+/// Does the work of [`do_find`] on `code`, located at `base`, which is the whole function if
+/// `exact`, and otherwise as far as to look for its end.
+fn scan(
+	name: &'static str,
+	code: &[u16],
+	base: usize,
+	exact: bool,
+	flag: Flag,
+) -> Result<Vec<Pos>, SearchError> {
+	let mut out = Vec::new();
+	// The register holding the loaded flag and the branch conditions, if any, and how many more
+	// instructions to look for the next step of the pattern in
+	let mut load: Option<(u32, u32, usize)> = None;
+	let mut test: Option<((u32, u32), usize)> = None;
+	let mut i = 0;
+	// An instruction cut off by the end of `code` is as far as it goes
+	while let Some((insn, len)) = read(&code[i..]) {
+		let next = i + len / 2;
+		if let Some((conds, _)) = test {
+			let site = match patch_branch(insn, len, conds) {
+				Some(patched) => Some((i, insn, patched, len)),
+				None => read(&code[next..]).and_then(|(branch, branch_len)| {
+					match patch_it(insn, len, (branch, branch_len), conds)? {
+						(true, patched) => Some((next, branch, patched, branch_len)),
+						(false, patched) => Some((i, insn, patched, len)),
+					}
+				}),
+			};
+			if let Some((at, orig, patched, len)) = site {
+				out.push(Pos {
+					addr: base + at * 2,
+					orig,
+					patched,
+					len,
+				});
+				test = None;
+			}
+		}
+		if let Some((rt, bit, _)) = load {
+			if let Some(conds) = test_bit(insn, len, rt, bit) {
+				test = Some((conds, WINDOW));
+				load = None;
+			}
+		}
+		if let Some((rt, bit)) = load_bit(insn, len, flag) {
+			load = Some((rt, bit, WINDOW));
+		}
+		load = load.and_then(|(rt, bit, n)| n.checked_sub(1).map(|n| (rt, bit, n)));
+		test = test.and_then(|(conds, n)| n.checked_sub(1).map(|n| (conds, n)));
+		i = next;
+		if !exact
+			&& is_return(insn, len)
+			&& (code.get(i) == Some(&(NOP as u16)) || (base + i * 2) & 0xF == 0)
+		{
+			return Ok(out);
+		}
+	}
+	if !exact {
+		return Err(SearchError::PatternNotFound { function: name });
+	}
+	Ok(out)
+}
+
+/// The known offsets are not checked on this architecture yet, so the functions are always
+/// scanned.
+pub unsafe fn find_known(
+	_out: &mut Vec<Pos>,
+	_name: &'static str,
+	_ptr: *const u8,
+	_flag: Flag,
+	_offsets: &[usize],
+) -> Option<*const u8> {
+	None
+}
+
+/// Makes sure the new instructions are fetched, which user mode cannot do by itself on this
+/// architecture, so this goes through the runtime library's `__clear_cache`.
+pub unsafe fn flush_icache(ptr: *const u8, len: usize) {
+	unsafe extern "C" {
+		fn __clear_cache(start: *mut u8, end: *mut u8);
+	}
+	unsafe { __clear_cache(ptr as *mut u8, ptr.add(len) as *mut u8) }
+}
+
+#[cfg(test)]
+mod scan_test {
+	use super::*;
+
+	const FLAG: Flag = Flag {
+		offset: 0x12,
+		mask: 0x04,
+	};
+
+	/// `ldrb r0, [r1, #0x12]; lsls r0, r0, #29`, which puts the flag in the N flag.
+	const TEST: [u16; 2] = [0x7C88, 0x0740];
+
+	fn sites(code: &[u16], exact: bool) -> Result<Vec<(usize, u32, u32)>, SearchError> {
+		let sites = scan("test", code, 0, exact, FLAG)?;
+		Ok(sites
+			.iter()
+			.map(|pos| (pos.addr, pos.orig, pos.patched))
+			.collect())
+	}
+
+	#[test]
+	fn branch() {
+		// bmi 0; bx lr
+		let code = [TEST[0], TEST[1], 0xD400, 0x4770];
+		assert_eq!(sites(&code, true).unwrap(), [(4, 0xD400, NOP)]);
+	}
+
+	/// A `b` that only an `it` before it makes conditional.
+	#[test]
+	fn it_block() {
+		// it mi; b 0; bx lr
+		let code = [TEST[0], TEST[1], 0xBF48, 0xE000, 0x4770];
+		assert_eq!(sites(&code, true).unwrap(), [(6, 0xE000, NOP)]);
+		// it pl; b 0; bx lr
+		let code = [TEST[0], TEST[1], 0xBF58, 0xE000, 0x4770];
+		assert_eq!(sites(&code, true).unwrap(), [(4, 0xBF58, NOP)]);
+		// Not if the `it` covers more than the branch: itt mi
+		let code = [TEST[0], TEST[1], 0xBF44, 0xE000, 0x4770];
+		assert_eq!(sites(&code, true).unwrap(), []);
+	}
+
+	/// The scan should not read past the end of the code, whether it ends partway through an
+	/// instruction or right after a return.
+	#[test]
+	fn truncated() {
+		// The first half of `bmi.w`
+		let code = [TEST[0], TEST[1], 0xF100];
+		assert_eq!(sites(&code, true).unwrap(), []);
+		assert!(sites(&code, false).is_err());
+		// bmi 0; bx lr, not followed by anything
+		let code = [TEST[0], TEST[1], 0xD400, 0x4770];
+		assert!(sites(&code, false).is_err());
+		// ...but stopping at it if it ends a 16-byte block
+		let code = [
+			0xBF00, 0xBF00, 0xBF00, 0xBF00, TEST[0], TEST[1], 0xD400, 0x4770,
+		];
+		assert_eq!(sites(&code, false).unwrap(), [(12, 0xD400, NOP)]);
+	}
+}
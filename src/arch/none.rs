@@ -0,0 +1,48 @@
+use crate::patch::{Flag, SearchError};
+
+/// Number of bytes written at each patch site.
+pub const LEN: usize = 0;
+
+/// There is no matcher for this architecture, so nothing is ever found or patched.
+#[derive(Debug, Clone, Copy)]
+pub enum Pos {}
+
+impl Pos {
+	pub fn orig(&self) -> u32 {
+		match *self {}
+	}
+
+	pub fn ptr(&self) -> *const u8 {
+		match *self {}
+	}
+
+	pub unsafe fn is_written(&self, _on: bool) -> bool {
+		match *self {}
+	}
+
+	pub unsafe fn write(&self, _on: bool) {
+		match *self {}
+	}
+}
+
+pub unsafe fn do_find(
+	_out: &mut Vec<Pos>,
+	_name: &'static str,
+	ptr: *const u8,
+	_flag: Flag,
+) -> Result<*const u8, SearchError> {
+	Ok(ptr)
+}
+
+/// Nothing is ever found here, known or not.
+pub unsafe fn find_known(
+	_out: &mut Vec<Pos>,
+	_name: &'static str,
+	_ptr: *const u8,
+	_flag: Flag,
+	_offsets: &[usize],
+) -> Option<*const u8> {
+	None
+}
+
+pub unsafe fn flush_icache(_ptr: *const u8, _len: usize) {}
@@ -0,0 +1,224 @@
+use crate::patch::{Bound, Flag, SearchError};
+
+/// Number of bytes written at each patch site.
+///
+/// Compressed branches are only two bytes, but protecting a few extra bytes is harmless.
+pub const LEN: usize = 4;
+
+const NOP: u32 = 0x00000013;
+const C_NOP: u32 = 0x0001;
+const RET: u32 = 0x00008067;
+const C_RET: u32 = 0x8082;
+
+/// How many instructions may separate the steps of the pattern.
+const WINDOW: usize = 4;
+
+/// A `beqz`/`bnez` on the result of masking the alternate flag with `andi`.
+///
+/// A `bnez` is replaced with a `nop` and a `beqz` with an unconditional `j`, so that the branch
+/// always goes where it would if the flag were clear.
+#[derive(Debug, Clone, Copy)]
+pub struct Pos {
+	addr: usize,
+	orig: u32,
+	patched: u32,
+	len: usize,
+}
+
+impl Pos {
+	/// The original instruction at the site.
+	pub fn orig(&self) -> u32 {
+		self.orig
+	}
+
+	pub fn ptr(&self) -> *const u8 {
+		self.addr as *const u8
+	}
+
+	/// Whether the site already holds what `write(on)` would write.
+	pub unsafe fn is_written(&self, on: bool) -> bool {
+		let insn = if on { self.patched } else { self.orig };
+		let ptr = self.addr as *const u16;
+		unsafe { *ptr == insn as u16 && (self.len == 2 || *ptr.add(1) == (insn >> 16) as u16) }
+	}
+
+	pub unsafe fn write(&self, on: bool) {
+		let insn = if on { self.patched } else { self.orig };
+		let ptr = self.addr as *mut u16;
+		unsafe {
+			ptr.write(insn as u16);
+			if self.len == 4 {
+				ptr.add(1).write((insn >> 16) as u16);
+			}
+		}
+	}
+}
+
+unsafe fn read(ptr: *const u16) -> (u32, usize) {
+	unsafe {
+		let lo = *ptr as u32;
+		if lo & 3 == 3 {
+			(lo | (*ptr.add(1) as u32) << 16, 4)
+		} else {
+			(lo, 2)
+		}
+	}
+}
+
+fn sext(value: u32, bits: u32) -> i32 {
+	(value << (32 - bits)) as i32 >> (32 - bits)
+}
+
+/// If `insn` loads from `imm(rs1)`, returns the destination register and which bit of it holds
+/// the flag, if the flag is within the loaded range.
+fn load_bit(insn: u32, flag: Flag) -> Option<(u32, u32)> {
+	if insn & 0x7F != 0b0000011 {
+		return None;
+	}
+	let size = match (insn >> 12) & 7 {
+		0b000 | 0b100 => 1, // lb, lbu
+		0b001 | 0b101 => 2, // lh, lhu
+		0b010 | 0b110 => 4, // lw, lwu
+		0b011 => 8,         // ld
+		_ => return None,
+	};
+	let start = usize::try_from(sext(insn >> 20, 12)).ok()?;
+	(start..start + size).contains(&flag.offset).then(|| {
+		(
+			(insn >> 7) & 0x1F,
+			(flag.offset - start) as u32 * 8 + flag.mask.trailing_zeros(),
+		)
+	})
+}
+
+/// If `insn` is `andi rd, rs1, 1 << bit`, returns `rd`.
+fn andi_bit(insn: u32, rs1: u32, bit: u32) -> Option<u32> {
+	(insn & 0x707F == 0x7013
+		&& (insn >> 15) & 0x1F == rs1
+		&& bit < 11
+		&& sext(insn >> 20, 12) == 1 << bit)
+		.then_some((insn >> 7) & 0x1F)
+}
+
+/// If `insn` is a `beqz`/`bnez` on `rs1`, returns the replacement instruction.
+fn patch_branch(insn: u32, len: usize, rs1: u32) -> Option<u32> {
+	if len == 4 {
+		if insn & 0x01F0607F != 0x00000063 || (insn >> 15) & 0x1F != rs1 {
+			return None;
+		}
+		if insn & 0x1000 != 0 {
+			return Some(NOP);
+		}
+		let imm = (insn >> 31) << 12
+			| ((insn >> 7) & 1) << 11
+			| ((insn >> 25) & 0x3F) << 5
+			| ((insn >> 8) & 0xF) << 1;
+		let imm = sext(imm, 13) as u32;
+		Some(
+			((imm >> 20) & 1) << 31
+				| ((imm >> 1) & 0x3FF) << 21
+				| ((imm >> 11) & 1) << 20
+				| ((imm >> 12) & 0xFF) << 12
+				| 0x6F,
+		)
+	} else {
+		if insn & 0xC003 != 0xC001 || ((insn >> 7) & 7) + 8 != rs1 {
+			return None;
+		}
+		if insn & 0x2000 != 0 {
+			return Some(C_NOP);
+		}
+		let imm = ((insn >> 12) & 1) << 8
+			| ((insn >> 10) & 3) << 3
+			| ((insn >> 5) & 3) << 6
+			| ((insn >> 3) & 3) << 1
+			| ((insn >> 2) & 1) << 5;
+		let imm = sext(imm, 9) as u32;
+		Some(
+			((imm >> 11) & 1) << 12
+				| ((imm >> 4) & 1) << 11
+				| ((imm >> 8) & 3) << 9
+				| ((imm >> 10) & 1) << 8
+				| ((imm >> 6) & 1) << 7
+				| ((imm >> 7) & 1) << 6
+				| ((imm >> 1) & 7) << 3
+				| ((imm >> 5) & 1) << 2
+				| 0xA001,
+		)
+	}
+}
+
+/// Scans a function for a load of the flag, an `andi` isolating it, and a `beqz`/`bnez` on the
+/// result, stopping at the end of the function if its size is known, and otherwise at a `ret` that
+/// ends on a 16-byte boundary.
+pub unsafe fn do_find(
+	out: &mut Vec<Pos>,
+	name: &'static str,
+	ptr: *const u8,
+	flag: Flag,
+) -> Result<*const u8, SearchError> {
+	unsafe {
+		let n = out.len();
+		let start = ptr;
+		let mut ptr = ptr as *const u16;
+		// The register holding the loaded flag and the masked flag, if any, and how many more
+		// instructions to look for the next step of the pattern in
+		let mut load: Option<(u32, u32, usize)> = None;
+		let mut mask: Option<(u32, usize)> = None;
+		let bound = Bound::new(name, ptr as *const u8);
+		while !bound.reached(name, ptr)? {
+			let (insn, len) = read(ptr);
+			if let Some((rd, _)) = mask {
+				if let Some(patched) = patch_branch(insn, len, rd) {
+					out.push(Pos {
+						addr: ptr as usize,
+						orig: insn,
+						patched,
+						len,
+					});
+					mask = None;
+				}
+			}
+			if let Some((rd, bit, _)) = load {
+				if let Some(rd) = andi_bit(insn, rd, bit) {
+					mask = Some((rd, WINDOW));
+					load = None;
+				}
+			}
+			if let Some(l) = (len == 4).then(|| load_bit(insn, flag)).flatten() {
+				load = Some((l.0, l.1, WINDOW));
+			}
+			load = load.and_then(|(rd, bit, n)| n.checked_sub(1).map(|n| (rd, bit, n)));
+			mask = mask.and_then(|(rd, n)| n.checked_sub(1).map(|n| (rd, n)));
+			ptr = ptr.byte_add(len);
+			if !bound.exact && (insn == RET || insn == C_RET) && (ptr as usize & 0xF) == 0 {
+				break;
+			}
+		}
+		if out.len() == n {
+			return Err(SearchError::PatternNotFound { function: name });
+		}
+		Ok(start)
+	}
+}
+
+/// The known offsets are not checked on this architecture yet, so the functions are always
+/// scanned.
+pub unsafe fn find_known(
+	_out: &mut Vec<Pos>,
+	_name: &'static str,
+	_ptr: *const u8,
+	_flag: Flag,
+	_offsets: &[usize],
+) -> Option<*const u8> {
+	None
+}
+
+/// Makes sure the new instructions are fetched, which user mode cannot do by itself on this
+/// architecture, so this goes through the runtime library's `__clear_cache`.
+pub unsafe fn flush_icache(ptr: *const u8, len: usize) {
+	unsafe extern "C" {
+		fn __clear_cache(start: *mut u8, end: *mut u8);
+	}
+	unsafe { __clear_cache(ptr as *mut u8, ptr.add(len) as *mut u8) }
+}
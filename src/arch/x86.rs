@@ -0,0 +1,718 @@
+use crate::patch::{is_part_of, readable_end, symbol_size, Bound, Flag, SearchError};
+
+#[cfg(feature = "decode")]
+#[path = "x86_decode.rs"]
+mod decode;
+
+/// Number of bytes written at each patch site.
+pub const LEN: usize = 1;
+
+/// The immediate of an instruction that tests the alternate flag, as recognized by one of the
+/// [`Variant`]s. The encodings are the same in 32- and 64-bit mode, save for an optional REX prefix.
+///
+/// Zeroing it makes the test always fail, so the non-alternate branch is taken. Disabling the
+/// patch writes back the mask, which is what the byte originally held even if it was found
+/// already zeroed.
+#[derive(Debug, Clone, Copy)]
+pub struct Pos {
+	addr: usize,
+	orig: u8,
+	/// Which encoding was matched. This is only shown in the `Debug` output for now.
+	#[allow(dead_code)]
+	variant: Variant,
+}
+
+impl Pos {
+	/// The original immediate at the site.
+	pub fn orig(&self) -> u32 {
+		self.orig.into()
+	}
+
+	pub fn ptr(&self) -> *const u8 {
+		self.addr as *const u8
+	}
+
+	fn value(&self, on: bool) -> u8 {
+		if on {
+			0
+		} else {
+			self.orig
+		}
+	}
+
+	/// Whether the site already holds what `write(on)` would write.
+	pub unsafe fn is_written(&self, on: bool) -> bool {
+		unsafe { *(self.addr as *const u8) == self.value(on) }
+	}
+
+	pub unsafe fn write(&self, on: bool) {
+		unsafe { (self.addr as *mut u8).write(self.value(on)) }
+	}
+}
+
+/// The ways the flag test is known to be encoded, depending on the compiler version and the
+/// optimization level `std` was built with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+	/// `test byte [reg+disp8], mask`
+	TestByte,
+	/// `test dword [reg+disp8], mask << n`, testing the flag as part of an aligned dword.
+	TestDword,
+	/// `movzx reg, byte [reg+disp8]` directly followed by a `test` or `and` of the register with
+	/// the mask.
+	LoadMask,
+}
+
+type Matcher = fn(&[u8], Flag) -> Option<usize>;
+
+/// The variants in the order they are tried, with functions that, given the code starting at the
+/// opcode, return the offset of the byte to patch.
+const VARIANTS: [(Variant, Matcher); 3] = [
+	(Variant::TestByte, test_byte),
+	(Variant::TestDword, test_dword),
+	(Variant::LoadMask, load_mask),
+];
+
+const ENDBR: [u8; 4] = [
+	0xF3,
+	0x0F,
+	0x1E,
+	if cfg!(target_arch = "x86_64") {
+		0xFA
+	} else {
+		0xFB
+	},
+];
+
+/// Follows unconditional jumps at the start of a function, as in thunks, to the real body.
+///
+/// Handles `jmp rel8`, `jmp rel32` and `jmp [rip+disp32]` (`jmp [disp32]` in 32-bit mode),
+/// optionally preceded by an `endbr` and a `bnd` prefix. When `std` is linked dynamically, as with
+/// `-C prefer-dynamic`, this is how the PLT stub that a function pointer may point to is followed
+/// into the shared library, so that it is that which gets patched and not the stub.
+unsafe fn follow(name: &'static str, mut ptr: *const u8) -> Result<*const u8, SearchError> {
+	unsafe {
+		for _ in 0..8 {
+			let insn = if *(ptr as *const [u8; 4]) == ENDBR {
+				ptr.add(4)
+			} else {
+				ptr
+			};
+			let insn = if *insn == 0xF2 { insn.add(1) } else { insn };
+			ptr = match *insn {
+				0xEB => insn.add(2).offset(*insn.add(1) as i8 as isize),
+				0xE9 => insn
+					.add(5)
+					.offset((insn.add(1) as *const i32).read_unaligned() as isize),
+				0xFF if *insn.add(1) == 0x25 => {
+					let disp = (insn.add(2) as *const i32).read_unaligned();
+					let slot = if cfg!(target_arch = "x86_64") {
+						insn.add(6).offset(disp as isize)
+					} else {
+						disp as usize as *const u8
+					};
+					(slot as *const *const u8).read_unaligned()
+				}
+				_ => return Ok(ptr),
+			};
+		}
+		Err(SearchError::PatternNotFound { function: name })
+	}
+}
+
+/// If `code` starts with a ModRM byte for `[reg+offset]`, returns its reg field and the offset
+/// following the displacement.
+///
+/// If the offset is zero, as may happen with the 32-bit layout, the displacement may be omitted.
+fn mem_operand(code: &[u8], offset: usize) -> Option<(u8, usize)> {
+	let modrm = *code.first()?;
+	if modrm & 0x07 == 0x04 {
+		return None;
+	}
+	let next = match modrm & 0xC0 {
+		0x40 if *code.get(1)? as usize == offset => 2,
+		0x00 if modrm & 0x07 != 0x05 && offset == 0 => 1,
+		_ => return None,
+	};
+	Some(((modrm >> 3) & 7, next))
+}
+
+/// Whether `imm` is the mask, or zero if the site has already been patched, such as by another
+/// copy of this crate. Either way, the original value is the mask.
+fn is_mask(imm: u8, flag: Flag) -> bool {
+	imm == flag.mask || imm == 0
+}
+
+/// Matches `f6 /0 <offset> <mask>`.
+fn test_byte(code: &[u8], flag: Flag) -> Option<usize> {
+	if *code.first()? != 0xF6 {
+		return None;
+	}
+	let (0, imm) = mem_operand(&code[1..], flag.offset)? else {
+		return None;
+	};
+	is_mask(*code.get(1 + imm)?, flag).then_some(1 + imm)
+}
+
+/// Matches `f7 /0 <offset & !3> <mask << 8 * (offset & 3)>`, returning the byte of the immediate
+/// that holds the mask.
+fn test_dword(code: &[u8], flag: Flag) -> Option<usize> {
+	if *code.first()? != 0xF7 {
+		return None;
+	}
+	let shift = flag.offset & 3;
+	let (0, imm) = mem_operand(&code[1..], flag.offset - shift)? else {
+		return None;
+	};
+	let imm = 1 + imm;
+	let value = u32::from_le_bytes(code.get(imm..imm + 4)?.try_into().unwrap());
+	(value == 0 || value == (flag.mask as u32) << (8 * shift)).then_some(imm + shift)
+}
+
+/// Matches `0f b6 /r <offset>` followed by `test` or `and` of the same register with the mask,
+/// returning the immediate of the latter.
+///
+/// Only the low three bits of the register number are compared, as any REX prefix on the load
+/// is before `code`.
+fn load_mask(code: &[u8], flag: Flag) -> Option<usize> {
+	if code.get(..2)? != [0x0F, 0xB6] {
+		return None;
+	}
+	let (reg, next) = mem_operand(&code[2..], flag.offset)?;
+	let mut next = 2 + next;
+	if *code.get(next)? & 0xF0 == 0x40 && cfg!(target_arch = "x86_64") {
+		next += 1;
+	}
+	let imm = match (*code.get(next)?, *code.get(next + 1)?) {
+		(0xA8 | 0x24, _) if reg == 0 => next + 1, // test al / and al
+		(0xF6, r) if r == 0xC0 | reg => next + 2, // test r8
+		(0x80 | 0x83, r) if r == 0xE0 | reg => next + 2, // and r8 / and r32, imm8
+		_ => return None,
+	};
+	is_mask(*code.get(imm)?, flag).then_some(imm)
+}
+
+/// Tries each variant at the start of `code`, returning the first that matches.
+fn match_at(code: &[u8], flag: Flag) -> Option<(Variant, usize)> {
+	VARIANTS
+		.iter()
+		.find_map(|&(variant, matcher)| matcher(code, flag).map(|imm| (variant, imm)))
+}
+
+/// Scans a function for the flag test, stopping at what looks like the end of it. If the function
+/// starts with a jump, the jump is followed first, and the address it leads to is returned.
+///
+/// If nothing is found, the jumps out of the function are followed, since PGO and BOLT may split
+/// off the parts of it that are rarely run. This needs the `decode` feature, since otherwise jumps
+/// cannot be told apart from the bytes of other instructions. Only the jumps that the symbol table
+/// shows to stay within the function, or to lead to a part split off from it, are followed, and
+/// not tail calls to other functions.
+pub unsafe fn do_find(
+	out: &mut Vec<Pos>,
+	name: &'static str,
+	ptr: *const u8,
+	flag: Flag,
+) -> Result<*const u8, SearchError> {
+	unsafe {
+		let n = out.len();
+		let start = follow(name, ptr)?;
+		let mut jumps = find_from(out, name, start, flag)?;
+		jumps.retain(|&target| is_part_of(target, start as usize));
+		for &fragment in &jumps {
+			if out.len() != n {
+				break;
+			}
+			let _ = find_from(out, name, fragment as *const u8, flag);
+		}
+		if out.len() == n {
+			return Err(match jumps.first() {
+				Some(&fragment) => SearchError::SplitPatternNotFound {
+					function: name,
+					entry: start as usize,
+					fragment,
+				},
+				None => SearchError::PatternNotFound { function: name },
+			});
+		}
+		Ok(start)
+	}
+}
+
+/// Scans the code at `start` up to what looks like the end of the function, returning the targets
+/// of the jumps out of it.
+unsafe fn find_from(
+	out: &mut Vec<Pos>,
+	name: &'static str,
+	start: *const u8,
+	flag: Flag,
+) -> Result<Vec<usize>, SearchError> {
+	unsafe {
+		let bound = Bound::new(name, start);
+		// Without an exact size, this may cover more than the function, but not more than is mapped
+		let code = std::slice::from_raw_parts(start, bound.end - start as usize);
+		find_in(out, name, code, start as usize, bound.exact, flag)
+	}
+}
+
+/// Like [`do_find`], but only checks that the flag test is at each of `offsets` from the start of
+/// the function, returning `None` if any of them does not hold one.
+pub unsafe fn find_known(
+	out: &mut Vec<Pos>,
+	name: &'static str,
+	ptr: *const u8,
+	flag: Flag,
+	offsets: &[usize],
+) -> Option<*const u8> {
+	unsafe {
+		let start = follow(name, ptr).ok()?;
+		// An offset past the end of what can be read has to be wrong anyway
+		let needed = start as usize + offsets.iter().max().map_or(0, |&offset| offset + 4);
+		let end = match symbol_size(start as usize) {
+			Some(size) => start as usize + size,
+			None => readable_end(start as usize, needed),
+		};
+		if needed > end {
+			return None;
+		}
+		let mut found = Vec::new();
+		for &offset in offsets {
+			// The opcode is at most eight bytes before the immediate, which in turn may be followed
+			// by three more bytes of a dword
+			let (variant, _) = (1..=offset.min(8)).find_map(|back| {
+				let code = std::slice::from_raw_parts(start.add(offset - back), back + 4);
+				match_at(code, flag).filter(|&(_, imm)| imm == back)
+			})?;
+			found.push(Pos {
+				addr: start as usize + offset,
+				orig: flag.mask,
+				variant,
+			});
+		}
+		out.extend(found);
+		Some(start)
+	}
+}
+
+/// Does the work of [`find_from`] on `code`, located at `base`, which is the whole function if
+/// `exact`, and otherwise as far as to look for its end.
+fn find_in(
+	out: &mut Vec<Pos>,
+	name: &'static str,
+	code: &[u8],
+	base: usize,
+	exact: bool,
+	flag: Flag,
+) -> Result<Vec<usize>, SearchError> {
+	let scan = scan(name, code, base, exact, flag)?;
+	for (variant, imm) in scan.sites {
+		out.push(Pos {
+			addr: base + imm,
+			orig: flag.mask,
+			variant,
+		});
+	}
+	Ok(scan.jumps)
+}
+
+/// Scans `code` as if it were a function, returning the offsets of the immediates that would be
+/// patched, without following any jumps.
+pub fn scan_code(code: &[u8], exact: bool, flag: Flag) -> Result<Vec<usize>, SearchError> {
+	let scan = scan("code", code, 0, exact, flag)?;
+	Ok(scan.sites.into_iter().map(|(_, imm)| imm).collect())
+}
+
+/// What [`scan`] found in a function.
+#[derive(Debug, Default)]
+struct Scan {
+	/// The variant and offset of the immediate of each flag test.
+	sites: Vec<(Variant, usize)>,
+	/// The targets of unconditional jumps out of the function, which are only found with
+	/// `decode`.
+	jumps: Vec<usize>,
+}
+
+/// Whether the bytes just before `code.len()` look like the start of an instruction whose
+/// immediate or displacement holds all of the `len` bytes from there, so that a match there is
+/// only a constant.
+///
+/// This only knows of a few common instructions: `mov reg, imm`, and loads, stores and `lea` with
+/// a 32-bit displacement, such as `mov eax, [rip+disp32]`.
+#[cfg(not(feature = "decode"))]
+fn in_immediate(code: &[u8], len: usize) -> bool {
+	let at = |back: usize| code.len().checked_sub(back).map(|i| code[i]);
+	let rex_w = |back: usize| {
+		cfg!(target_arch = "x86_64") && at(back + 1).is_some_and(|rex| rex & 0xF8 == 0x48)
+	};
+	(1..=8).any(|back| {
+		let Some(byte) = at(back) else {
+			return false;
+		};
+		// The immediate or displacement starts right after `byte`
+		let fits = |size: usize| back - 1 + len <= size;
+		let mov_imm = (0xB8..=0xBF).contains(&byte) && fits(if rex_w(back) { 8 } else { 4 });
+		let disp32 = (byte & 0xC7 == 0x05 || byte & 0xC0 == 0x80 && byte & 0x07 != 0x04)
+			&& at(back + 1).is_some_and(|opcode| matches!(opcode, 0x89 | 0x8B | 0x8D))
+			&& fits(4);
+		mov_imm || disp32
+	})
+}
+
+/// The length and displacement of the relative jump that `code` starts with, if it looks like
+/// one, and whether it is unconditional.
+#[cfg(not(feature = "decode"))]
+fn jump_at(code: &[u8]) -> Option<(usize, isize, bool)> {
+	let rel32 =
+		|at: usize| Some(i32::from_le_bytes(code.get(at..at + 4)?.try_into().ok()?) as isize);
+	match *code {
+		// `jcc rel8`, `jmp rel8`
+		[opcode @ (0x70..=0x7F | 0xEB), rel, ..] => Some((2, rel as i8 as isize, opcode == 0xEB)),
+		// `jmp rel32`
+		[0xE9, ..] => Some((5, rel32(1)?, true)),
+		// `jcc rel32`
+		[0x0F, 0x80..=0x8F, ..] => Some((6, rel32(2)?, false)),
+		_ => None,
+	}
+}
+
+/// Looks for the pattern at every byte, until the end of the function if its size is known.
+///
+/// Otherwise, it stops at a `ret`, `int3` or `jmp` that ends a 16-byte block, unless something
+/// that looks like a jump leads past it, like the scan with `decode` does. Without decoding, the
+/// jumps cannot be told apart from the bytes of other instructions, so any bytes that look like
+/// one count, which can only make the scan go on for longer.
+///
+/// Matches that look like the immediate of another instruction are skipped; see [`in_immediate`].
+#[cfg(not(feature = "decode"))]
+fn scan(
+	name: &'static str,
+	code: &[u8],
+	base: usize,
+	exact: bool,
+	flag: Flag,
+) -> Result<Scan, SearchError> {
+	let mut out = Scan::default();
+	// The furthest that a jump within the function leads
+	let mut reach = 0;
+	// Where the last unconditional jump ends
+	let mut jumped = None;
+	for i in 0..code.len() {
+		out.sites.extend(
+			match_at(&code[i..], flag)
+				.filter(|&(_, imm)| !in_immediate(&code[..i], imm + 1))
+				.map(|(variant, imm)| (variant, i + imm)),
+		);
+		if let Some((len, rel, unconditional)) = jump_at(&code[i..]) {
+			let target = (i + len).wrapping_add_signed(rel);
+			if target < code.len() {
+				reach = reach.max(target);
+			}
+			if unconditional {
+				jumped = Some(i + len - 1);
+			}
+		}
+		let end = matches!(code[i], 0xC3 | 0xCC) || jumped == Some(i);
+		if !exact && reach <= i && (base + i) & 0xF == 0xF && end {
+			return Ok(out);
+		}
+	}
+	if !exact {
+		return Err(SearchError::PatternNotFound { function: name });
+	}
+	Ok(out)
+}
+
+/// Steps through the function one instruction at a time, so that only actual instructions are
+/// matched, until the end of the function if its size is known.
+///
+/// Otherwise, it stops at the first `ret`, `int3` or `jmp` that no jump seen so far leads past,
+/// since the code after one is only part of the function if something jumps there. Jumps through a
+/// register, such as for a `match` compiled to a jump table, cannot be followed this way.
+#[cfg(feature = "decode")]
+fn scan(
+	name: &'static str,
+	code: &[u8],
+	base: usize,
+	exact: bool,
+	flag: Flag,
+) -> Result<Scan, SearchError> {
+	let mut out = Scan::default();
+	// The furthest that a jump within the function leads
+	let mut reach = 0;
+	let mut i = 0;
+	while i < code.len() {
+		let Some(insn) = decode::decode(&code[i..code.len().min(i + 15)]) else {
+			return Err(SearchError::PatternNotFound { function: name });
+		};
+		if let (0 | 1, Some(modrm)) = (insn.map, insn.modrm) {
+			let at = i + modrm - 1 - insn.map as usize;
+			out.sites
+				.extend(match_at(&code[at..], flag).map(|(variant, imm)| (variant, at + imm)));
+		}
+		let at = i;
+		i += insn.len;
+		let rel = match (insn.map, insn.opcode) {
+			// `jcc rel8`, `jmp rel8`
+			(0, 0x70..=0x7F | 0xEB) => Some(code[i - 1] as i8 as isize),
+			// `jcc rel32`, `jmp rel32`
+			(1, 0x80..=0x8F) | (0, 0xE9) => {
+				Some(i32::from_le_bytes(code[i - 4..i].try_into().unwrap()) as isize)
+			}
+			_ => None,
+		};
+		if let Some(rel) = rel {
+			let target = (base + i).wrapping_add_signed(rel);
+			if (base..base + code.len()).contains(&target) {
+				reach = reach.max(target - base);
+			} else if insn.map == 0 && insn.opcode == 0xE9 {
+				out.jumps.push(target);
+			}
+		}
+		if !exact
+			&& reach <= at
+			&& insn.map == 0
+			&& matches!(insn.opcode, 0xC3 | 0xCC | 0xE9 | 0xEB)
+		{
+			return Ok(out);
+		}
+	}
+	if !exact {
+		return Err(SearchError::PatternNotFound { function: name });
+	}
+	Ok(out)
+}
+
+/// x86 keeps the instruction cache coherent with writes, so nothing needs to be done.
+pub unsafe fn flush_icache(_ptr: *const u8, _len: usize) {}
+
+#[cfg(test)]
+#[test]
+fn variants() {
+	let flag = Flag {
+		offset: 0x12,
+		mask: 0x80,
+	};
+	let cases: &[(&[u8], Variant, usize)] = &[
+		(&[0xF6, 0x46, 0x12, 0x80], Variant::TestByte, 3), // test byte [rsi+0x12], 0x80
+		(&[0xF7, 0x46, 0x10, 0, 0, 0x80, 0], Variant::TestDword, 5), // test dword [rsi+0x10], 0x800000
+		(&[0x0F, 0xB6, 0x46, 0x12, 0xA8, 0x80], Variant::LoadMask, 5), // movzx eax, [rsi+0x12]; test al
+		(
+			&[0x0F, 0xB6, 0x4E, 0x12, 0x83, 0xE1, 0x80],
+			Variant::LoadMask,
+			6,
+		), // ...; and ecx, -0x80
+	];
+	for &(code, variant, at) in cases {
+		assert_eq!(match_at(code, flag), Some((variant, at)), "{code:02X?}");
+		let mut patched = code.to_vec();
+		patched[at] = 0;
+		assert_eq!(match_at(&patched, flag), Some((variant, at)), "{code:02X?}");
+	}
+	let code = [0x0F, 0xB6, 0x4E, 0x12, 0xA8, 0x80]; // movzx ecx, ...; test al
+	assert_eq!(match_at(&code, flag), None);
+	assert_eq!(match_at(&code[..5], flag), None);
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+#[test]
+fn plt() {
+	let body = [0x90u8; 4];
+	// endbr64; bnd jmp [rip+0], followed by the GOT slot it reads
+	let mut stub = vec![0xF3, 0x0F, 0x1E, 0xFA, 0xF2, 0xFF, 0x25, 0, 0, 0, 0];
+	stub.extend_from_slice(&(body.as_ptr() as usize).to_le_bytes());
+	assert_eq!(
+		unsafe { follow("plt", stub.as_ptr()) }.unwrap(),
+		body.as_ptr()
+	);
+}
+
+#[cfg(test)]
+mod scan_test {
+	use proptest::prelude::*;
+
+	use super::*;
+
+	const FLAG: Flag = Flag {
+		offset: 0x12,
+		mask: 0x04,
+	};
+
+	#[derive(Debug, Clone, Copy)]
+	enum Chunk {
+		Nop,
+		/// `test byte [reg+0x12], 4`, with a ModRM byte of `0x40 | reg`
+		Site(u8),
+		/// `mov eax, [rip+disp32]`, with the pattern as the displacement
+		Decoy(u8),
+		/// A `ret` in the middle of a 16-byte block
+		Ret,
+		/// `je` over a `ret` in the middle of a 16-byte block, so that the function goes on after it
+		Branch,
+	}
+
+	fn chunk() -> impl Strategy<Value = Chunk> {
+		let reg = prop_oneof![0..4u8, 5..8u8];
+		prop_oneof![
+			4 => Just(Chunk::Nop),
+			1 => reg.clone().prop_map(Chunk::Site),
+			1 => reg.prop_map(Chunk::Decoy),
+			1 => Just(Chunk::Ret),
+			1 => Just(Chunk::Branch),
+		]
+	}
+
+	/// Assembles the chunks, returning the code, the offsets of the immediates that should be
+	/// found in it, and the offset of the first `ret` that the scan may stop at.
+	fn assemble(chunks: &[Chunk]) -> (Vec<u8>, Vec<usize>, Option<usize>) {
+		let mut code = Vec::new();
+		let mut sites = Vec::new();
+		let mut ret = None;
+		for &chunk in chunks {
+			match chunk {
+				Chunk::Nop => code.push(0x90),
+				Chunk::Site(reg) => {
+					code.extend([0xF6, 0x40 | reg, 0x12, 0x04]);
+					sites.push(code.len() - 1);
+				}
+				Chunk::Decoy(reg) => {
+					code.extend([0x8B, 0x05, 0xF6, 0x40 | reg, 0x12, 0x04]);
+				}
+				Chunk::Ret => {
+					if code.len() & 0xF == 0xF {
+						code.push(0x90);
+					}
+					ret.get_or_insert(code.len());
+					code.push(0xC3);
+				}
+				Chunk::Branch => {
+					if (code.len() + 2) & 0xF == 0xF {
+						code.push(0x90);
+					}
+					code.extend([0x74, 0x01, 0xC3]);
+				}
+			}
+		}
+		(code, sites, ret)
+	}
+
+	fn found(code: &[u8], exact: bool) -> Result<Vec<usize>, SearchError> {
+		let mut out = Vec::new();
+		find_in(&mut out, "test", code, 0, exact, FLAG)?;
+		if out.is_empty() {
+			return Err(SearchError::PatternNotFound { function: "test" });
+		}
+		Ok(out.iter().map(|pos| pos.addr).collect())
+	}
+
+	/// The pattern inside the immediate or displacement of another instruction should not be
+	/// found, whether or not the instructions are decoded.
+	#[test]
+	fn decoys() {
+		let cases: &[&[u8]] = &[
+			&[0xB8, 0xF6, 0x46, 0x12, 0x04], // mov eax, 0x041246F6
+			&[0x48, 0xB9, 0, 0, 0, 0xF6, 0x46, 0x12, 0x04, 0], // mov rcx, imm64
+			&[0x8B, 0x05, 0xF6, 0x46, 0x12, 0x04], // mov eax, [rip+0x041246F6]
+			&[0x48, 0x8D, 0x8F, 0xF6, 0x46, 0x12, 0x04], // lea rcx, [rdi+0x041246F6]
+		];
+		for &code in cases {
+			let mut code = code.to_vec();
+			code.push(0xC3);
+			assert!(found(&code, true).is_err(), "{code:02X?}");
+		}
+		// Not when the constant ends before the pattern does
+		let code = [0xB8, 1, 0, 0, 0, 0xF6, 0x46, 0x12, 0x04, 0xC3]; // mov eax, 1; test
+		assert_eq!(found(&code, true).unwrap(), [8]);
+	}
+
+	/// Without the size of the function, the scan should stop at a tail call, rather than go on
+	/// into the next function.
+	#[test]
+	fn tail_call() {
+		// test byte [rsi+0x12], 4; nop...; jmp 0x1000
+		let mut code = vec![0xF6, 0x46, 0x12, 0x04];
+		code.resize(11, 0x90);
+		code.extend([0xE9, 0xF0, 0x0F, 0, 0]);
+		// test byte [rdi+0x12], 4; ret; int3...
+		code.extend([0xF6, 0x47, 0x12, 0x04, 0xC3]);
+		code.resize(32, 0xCC);
+		assert_eq!(found(&code, false).unwrap(), [3]);
+		assert_eq!(found(&code, true).unwrap(), [3, 19]);
+		// Unless something jumps past it: jne 0x10
+		code[4..6].copy_from_slice(&[0x75, 0x0A]);
+		assert_eq!(found(&code, false).unwrap(), [3, 19]);
+	}
+
+	#[cfg(feature = "decode")]
+	#[test]
+	fn jumps() {
+		// jmp 0x1000; nop; ret; jmp 0x4; int3
+		let code = [
+			0xE9, 0xFB, 0x0F, 0, 0, 0x90, 0xC3, 0xE9, 0xF8, 0xFF, 0xFF, 0xFF, 0xCC,
+		];
+		let scan = scan("test", &code, 0, true, FLAG).unwrap();
+		assert!(scan.sites.is_empty());
+		assert_eq!(scan.jumps, [0x1000]);
+	}
+
+	/// A jump out of the function that does not lead to a part of it, like a tail call, is not
+	/// followed, even if the code there has the flag test.
+	#[cfg(feature = "decode")]
+	#[test]
+	fn tail_call_target() {
+		let mut code = [0xCC; 48];
+		// test byte [rsi+0x12], 4; ret
+		code[..5].copy_from_slice(&[0xF6, 0x46, 0x12, 0x04, 0xC3]);
+		// nop; jmp 0
+		code[32..38].copy_from_slice(&[0x90, 0xE9, 0xDA, 0xFF, 0xFF, 0xFF]);
+		let mut out = Vec::new();
+		let found = unsafe { do_find(&mut out, "test", code[32..].as_ptr(), FLAG) };
+		assert!(matches!(found, Err(SearchError::PatternNotFound { .. })));
+		assert!(out.is_empty());
+	}
+
+	proptest! {
+		#[test]
+		fn exact(chunks in prop::collection::vec(chunk(), 0..64)) {
+			let (code, sites, _) = assemble(&chunks);
+			match found(&code, true) {
+				Ok(found) => prop_assert_eq!(found, sites),
+				Err(_) => prop_assert!(sites.is_empty()),
+			}
+		}
+
+		/// Without the size of the function, the scan stops at a `ret` that ends a 16-byte block,
+		/// or with `decode`, at the first one that is not jumped over, missing anything after it.
+		#[test]
+		fn until_ret(
+			chunks in prop::collection::vec(chunk(), 0..64),
+			after in prop::collection::vec(chunk(), 0..16),
+		) {
+			let (mut code, mut sites, first) = assemble(&chunks);
+			let first = first.filter(|_| cfg!(feature = "decode"));
+			if let Some(first) = first {
+				sites.retain(|&site| site < first);
+			}
+			// At least one `nop` first, so that a jump at the end still leads into the code
+			let ret = (code.len() + 1) | 0xF;
+			code.resize(ret, 0x90);
+			code.push(0xC3);
+			code.extend(assemble(&after).0);
+			match found(&code, false) {
+				Ok(found) => prop_assert_eq!(found, sites),
+				Err(_) => prop_assert!(sites.is_empty()),
+			}
+			// If there is no `ret` before the limit, it gives up
+			if first.is_none() {
+				prop_assert!(found(&code[..ret], false).is_err());
+			}
+		}
+
+		/// Whatever the bytes, the scan should only find sites inside them, like the fuzz target
+		/// in `fuzz` checks at length.
+		#[test]
+		fn arbitrary(code in prop::collection::vec(any::<u8>(), 0..256), exact: bool) {
+			if let Ok(sites) = scan_code(&code, exact, FLAG) {
+				prop_assert!(sites.iter().all(|&site| site < code.len()));
+			}
+		}
+	}
+}
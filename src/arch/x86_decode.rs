@@ -0,0 +1,240 @@
+//! A minimal x86 instruction length decoder.
+//!
+//! This only decodes as much as is needed to step from one instruction to the next and to find
+//! the opcode and ModRM bytes; it does not care what the instructions do.
+
+const X64: bool = cfg!(target_arch = "x86_64");
+
+/// The parts of a decoded instruction that the matcher cares about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Insn {
+	/// Total length in bytes.
+	pub len: usize,
+	/// Which opcode map the opcode is in: 0 for one-byte opcodes, 1 for `0f`, 2 for `0f 38`,
+	/// and 3 for `0f 3a`.
+	pub map: u8,
+	pub opcode: u8,
+	/// Offset of the ModRM byte, if any.
+	pub modrm: Option<usize>,
+}
+
+#[derive(Clone, Copy)]
+enum Imm {
+	None,
+	Byte,
+	Word,
+	/// Two bytes with an operand size prefix, otherwise four
+	Full,
+	/// Like `Full`, but eight bytes with REX.W
+	Wide,
+	/// Eight bytes in 64-bit mode, otherwise four
+	Addr,
+	/// The immediate depends on the ModRM reg field (`f6`/`f7`)
+	Group3(bool),
+	/// `enter`
+	Enter,
+}
+
+/// Returns whether the one-byte opcode has a ModRM byte, and its immediate.
+fn one_byte(op: u8) -> Option<(bool, Imm)> {
+	Some(match op {
+		0x00..=0x3F => match op & 7 {
+			0..=3 => (true, Imm::None),
+			4 => (false, Imm::Byte),
+			5 => (false, Imm::Full),
+			_ if X64 => return None,
+			_ => (false, Imm::None),
+		},
+		0x40..=0x5F => (false, Imm::None),
+		0x60..=0x62 if X64 => return None,
+		0x60 | 0x61 => (false, Imm::None),
+		0x62 | 0x63 => (true, Imm::None),
+		0x68 => (false, Imm::Full),
+		0x69 => (true, Imm::Full),
+		0x6A => (false, Imm::Byte),
+		0x6B => (true, Imm::Byte),
+		0x6C..=0x6F => (false, Imm::None),
+		0x70..=0x7F => (false, Imm::Byte),
+		0x80 | 0x83 => (true, Imm::Byte),
+		0x81 => (true, Imm::Full),
+		0x82 if X64 => return None,
+		0x82 => (true, Imm::Byte),
+		0x84..=0x8F => (true, Imm::None),
+		0x9A if X64 => return None,
+		0x9A => (false, Imm::Word), // followed by a full offset, see below
+		0x90..=0x9F => (false, Imm::None),
+		0xA0..=0xA3 => (false, Imm::Addr),
+		0xA8 => (false, Imm::Byte),
+		0xA9 => (false, Imm::Full),
+		0xA4..=0xAF => (false, Imm::None),
+		0xB0..=0xB7 => (false, Imm::Byte),
+		0xB8..=0xBF => (false, Imm::Wide),
+		0xC0 | 0xC1 => (true, Imm::Byte),
+		0xC2 | 0xCA => (false, Imm::Word),
+		0xC3 | 0xC9 | 0xCB | 0xCC | 0xCE | 0xCF => (false, Imm::None),
+		0xC6 => (true, Imm::Byte),
+		0xC7 => (true, Imm::Full),
+		0xC8 => (false, Imm::Enter),
+		0xCD => (false, Imm::Byte),
+		0xD0..=0xD3 => (true, Imm::None),
+		0xD4 | 0xD5 if X64 => return None,
+		0xD4 | 0xD5 => (false, Imm::Byte),
+		0xD7 => (false, Imm::None),
+		0xD8..=0xDF => (true, Imm::None),
+		0xE0..=0xE7 => (false, Imm::Byte),
+		0xE8 | 0xE9 => (false, Imm::Full),
+		0xEB => (false, Imm::Byte),
+		0xEC..=0xEF => (false, Imm::None),
+		0xF1 | 0xF4 | 0xF5 => (false, Imm::None),
+		0xF6 => (true, Imm::Group3(false)),
+		0xF7 => (true, Imm::Group3(true)),
+		0xF8..=0xFD => (false, Imm::None),
+		0xFE | 0xFF => (true, Imm::None),
+		_ => return None,
+	})
+}
+
+/// Returns whether the `0f` opcode has a ModRM byte, and its immediate.
+fn two_byte(op: u8) -> Option<(bool, Imm)> {
+	Some(match op {
+		0x04 | 0x0A | 0x0C | 0x0F | 0x24..=0x27 | 0x36 | 0x39 | 0x3B..=0x3F => return None,
+		0x05..=0x09 | 0x0B | 0x0E | 0x30..=0x35 | 0x37 | 0x77 => (false, Imm::None),
+		0x70..=0x73 => (true, Imm::Byte),
+		0x80..=0x8F => (false, Imm::Full),
+		0xA0..=0xA2 | 0xA8..=0xAA | 0xC8..=0xCF => (false, Imm::None),
+		0xA4 | 0xAC | 0xBA | 0xC2 | 0xC4..=0xC6 => (true, Imm::Byte),
+		_ => (true, Imm::None),
+	})
+}
+
+/// Decodes the instruction at the start of `code`, returning `None` if it is not recognized or
+/// does not fit.
+pub fn decode(code: &[u8]) -> Option<Insn> {
+	let mut i = 0;
+	let mut opsize = false;
+	let mut addrsize = false;
+	let mut rex_w = false;
+	loop {
+		match *code.get(i)? {
+			0x66 => opsize = true,
+			0x67 => addrsize = true,
+			0xF0 | 0xF2 | 0xF3 | 0x26 | 0x2E | 0x36 | 0x3E | 0x64 | 0x65 => {}
+			_ => break,
+		}
+		i += 1;
+	}
+	if X64 && *code.get(i)? & 0xF0 == 0x40 {
+		rex_w = code[i] & 0x08 != 0;
+		i += 1;
+	}
+
+	let (map, opcode, has_modrm, imm) = match *code.get(i)? {
+		// VEX and EVEX; in 32-bit mode these are only prefixes if they would otherwise be
+		// followed by a register operand
+		0xC4 | 0xC5 | 0x62 if X64 || *code.get(i + 1)? >= 0xC0 => {
+			let (map, len) = match code[i] {
+				0xC5 => (1, 2),
+				0xC4 => (*code.get(i + 1)? & 0x1F, 3),
+				_ => (*code.get(i + 1)? & 0x07, 4),
+			};
+			i += len;
+			let opcode = *code.get(i)?;
+			match map {
+				1 => (1, opcode, true, two_byte(opcode)?.1),
+				2 => (2, opcode, true, Imm::None),
+				3 => (3, opcode, true, Imm::Byte),
+				_ => return None,
+			}
+		}
+		0x0F => match *code.get(i + 1)? {
+			0x38 => {
+				i += 2;
+				(2, *code.get(i)?, true, Imm::None)
+			}
+			0x3A => {
+				i += 2;
+				(3, *code.get(i)?, true, Imm::Byte)
+			}
+			opcode => {
+				i += 1;
+				let (has_modrm, imm) = two_byte(opcode)?;
+				(1, opcode, has_modrm, imm)
+			}
+		},
+		opcode => {
+			let (has_modrm, imm) = one_byte(opcode)?;
+			(0, opcode, has_modrm, imm)
+		}
+	};
+	i += 1;
+
+	let mut modrm = None;
+	let mut reg = 0;
+	if has_modrm {
+		let byte = *code.get(i)?;
+		modrm = Some(i);
+		reg = (byte >> 3) & 7;
+		let (md, rm) = (byte >> 6, byte & 7);
+		i += 1;
+		if md != 3 && rm == 4 {
+			let sib = *code.get(i)?;
+			i += 1;
+			if md == 0 && sib & 7 == 5 {
+				i += 4;
+			}
+		}
+		i += match (md, rm) {
+			(0, 5) => 4,
+			(1, _) => 1,
+			(2, _) => 4,
+			_ => 0,
+		};
+	}
+
+	let full = if opsize { 2 } else { 4 };
+	i += match imm {
+		Imm::None => 0,
+		Imm::Byte => 1,
+		Imm::Word if opcode == 0x9A && map == 0 => 2 + full,
+		Imm::Word => 2,
+		Imm::Full => full,
+		Imm::Wide if rex_w => 8,
+		Imm::Wide => full,
+		Imm::Addr if X64 && !addrsize => 8,
+		Imm::Addr => 4,
+		Imm::Group3(_) if reg > 1 => 0,
+		Imm::Group3(false) => 1,
+		Imm::Group3(true) => full,
+		Imm::Enter => 3,
+	};
+
+	(i <= code.len()).then_some(Insn {
+		len: i,
+		map,
+		opcode,
+		modrm,
+	})
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+#[test]
+fn lengths() {
+	let cases: &[&[u8]] = &[
+		&[0xC3],                                     // ret
+		&[0x41, 0xF6, 0x46, 0x12, 0x80],             // test byte [r14+0x12], 0x80
+		&[0x48, 0x8D, 0x3D, 0x45, 0x82, 0xF5, 0xFF], // lea rdi, [rip-0xa7dbb]
+		&[0x41, 0xFF, 0x50, 0x18],                   // call [r8+0x18]
+		&[0x0F, 0x85, 0x5C, 0xFF, 0xFF, 0xFF],       // jne rel32
+		&[0x48, 0x0F, 0x44, 0xCF],                   // cmove rcx, rdi
+		&[0x41, 0x0F, 0x10, 0x06],                   // movups xmm0, [r14]
+		&[0xC6, 0x45, 0xD7, 0x01],                   // mov byte [rbp-0x29], 1
+		&[0x48, 0xB8, 1, 2, 3, 4, 5, 6, 7, 8],       // mov rax, imm64
+		&[0x66, 0x0F, 0x1F, 0x44, 0x00, 0x00],       // nop word [rax+rax]
+		&[0xF7, 0x44, 0x24, 0x08, 1, 0, 0, 0],       // test dword [rsp+8], 1
+		&[0xF3, 0x0F, 0x1E, 0xFA],                   // endbr64
+	];
+	for code in cases {
+		assert_eq!(decode(code).map(|i| i.len), Some(code.len()), "{code:02X?}");
+	}
+	assert_eq!(decode(&[0x41, 0xF6, 0x46]), None);
+}
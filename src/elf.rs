@@ -0,0 +1,312 @@
+//! Reading the sizes and names of functions from the `.symtab` of the file they were loaded
+//! from, which unlike the dynamic symbol table also has the functions that are not exported, like
+//! most of `std`. It is left out of stripped binaries, in which case nothing is found.
+
+use std::ffi::{c_char, c_int, c_void, CStr, OsStr};
+use std::fs::File;
+use std::mem::size_of;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use crate::sync::Mutex;
+
+/// The functions of the last object looked into, so that its file is only read once.
+static FUNCTIONS: Mutex<Option<Functions>> = Mutex::new(None);
+
+struct Functions {
+	/// Where the object is loaded, relative to the addresses in the file.
+	bias: usize,
+	/// Sorted by address.
+	functions: Vec<Function>,
+	/// The string table that the names of the functions are in, which only x86 looks at.
+	#[cfg_attr(
+		not(any(target_arch = "x86", target_arch = "x86_64")),
+		allow(dead_code)
+	)]
+	names: Vec<u8>,
+}
+
+struct Function {
+	addr: usize,
+	size: usize,
+	/// The offset of the name in [`Functions::names`].
+	#[cfg_attr(
+		not(any(target_arch = "x86", target_arch = "x86_64")),
+		allow(dead_code)
+	)]
+	name: usize,
+}
+
+impl Functions {
+	/// The function that starts at `addr`.
+	fn at(&self, addr: usize) -> Option<&Function> {
+		let i = self
+			.functions
+			.binary_search_by_key(&addr, |f| f.addr)
+			.ok()?;
+		Some(&self.functions[i])
+	}
+
+	/// The function that `addr` is inside of.
+	#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+	fn containing(&self, addr: usize) -> Option<&Function> {
+		let i = self
+			.functions
+			.partition_point(|f| f.addr <= addr)
+			.checked_sub(1)?;
+		let function = &self.functions[i];
+		(addr < function.addr + function.size).then_some(function)
+	}
+
+	#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+	fn name(&self, function: &Function) -> &[u8] {
+		let name = self.names.get(function.name..).unwrap_or_default();
+		CStr::from_bytes_until_nul(name).map_or(&[], CStr::to_bytes)
+	}
+}
+
+#[cfg(target_pointer_width = "64")]
+#[repr(C)]
+pub(crate) struct Sym {
+	pub(crate) name: u32,
+	pub(crate) info: u8,
+	pub(crate) other: u8,
+	pub(crate) shndx: u16,
+	pub(crate) value: u64,
+	pub(crate) size: u64,
+}
+
+#[cfg(target_pointer_width = "32")]
+#[repr(C)]
+pub(crate) struct Sym {
+	pub(crate) name: u32,
+	pub(crate) value: u32,
+	pub(crate) size: u32,
+	pub(crate) info: u8,
+	pub(crate) other: u8,
+	pub(crate) shndx: u16,
+}
+
+#[repr(C)]
+struct Ehdr {
+	ident: [u8; 16],
+	kind: u16,
+	machine: u16,
+	version: u32,
+	entry: usize,
+	phoff: usize,
+	shoff: usize,
+	flags: u32,
+	ehsize: u16,
+	phentsize: u16,
+	phnum: u16,
+	shentsize: u16,
+	shnum: u16,
+	shstrndx: u16,
+}
+
+#[repr(C)]
+struct Shdr {
+	name: u32,
+	kind: u32,
+	flags: usize,
+	addr: usize,
+	offset: usize,
+	size: usize,
+	link: u32,
+	info: u32,
+	addralign: usize,
+	entsize: usize,
+}
+
+#[cfg(target_pointer_width = "64")]
+#[repr(C)]
+struct Phdr {
+	kind: u32,
+	flags: u32,
+	offset: u64,
+	vaddr: u64,
+	paddr: u64,
+	filesz: u64,
+	memsz: u64,
+	align: u64,
+}
+
+#[cfg(target_pointer_width = "32")]
+#[repr(C)]
+struct Phdr {
+	kind: u32,
+	offset: u32,
+	vaddr: u32,
+	paddr: u32,
+	filesz: u32,
+	memsz: u32,
+	flags: u32,
+	align: u32,
+}
+
+#[repr(C)]
+struct PhdrInfo {
+	addr: usize,
+	name: *const c_char,
+	phdr: *const Phdr,
+	phnum: u16,
+}
+
+/// The bit that is set in the address of Thumb functions on 32-bit Arm, but not where they start.
+pub(crate) const THUMB: usize = if cfg!(target_arch = "arm") { 1 } else { 0 };
+
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+
+/// Looks up the size of the function starting at `ptr` in the `.symtab` of the object it is in.
+pub(crate) fn symbol_size(ptr: usize) -> Option<usize> {
+	with_functions(ptr, |functions| Some(functions.at(ptr)?.size))?
+}
+
+/// Whether `target` is inside the function starting at `start`, or in a part of it that was split
+/// off, such as by PGO or BOLT, whose symbol is named after it with a suffix like `.cold`.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub(crate) fn is_part_of(target: usize, start: usize) -> bool {
+	with_functions(start, |functions| {
+		let (Some(function), Some(part)) = (functions.at(start), functions.containing(target))
+		else {
+			return false;
+		};
+		let name = functions.name(function);
+		let part = functions.name(part);
+		!name.is_empty()
+			&& part
+				.strip_prefix(name)
+				.is_some_and(|suffix| suffix.is_empty() || suffix[0] == b'.')
+	})
+	.unwrap_or(false)
+}
+
+/// Calls `f` with the functions of the object that `ptr` is in, reading them first if they are
+/// not the ones kept from last time.
+fn with_functions<R>(ptr: usize, f: impl FnOnce(&Functions) -> R) -> Option<R> {
+	let (bias, path) = object(ptr)?;
+	let mut functions = FUNCTIONS.lock();
+	if functions
+		.as_ref()
+		.is_none_or(|functions| functions.bias != bias)
+	{
+		*functions = Some(read_functions(&path, bias)?);
+	}
+	Some(f(functions.as_ref()?))
+}
+
+/// Finds the loaded object that `ptr` is in, returning its load bias and the file it was loaded
+/// from.
+fn object(ptr: usize) -> Option<(usize, PathBuf)> {
+	unsafe extern "C" {
+		fn dl_iterate_phdr(
+			callback: unsafe extern "C" fn(*mut PhdrInfo, usize, *mut c_void) -> c_int,
+			data: *mut c_void,
+		) -> c_int;
+	}
+	unsafe extern "C" fn callback(info: *mut PhdrInfo, _size: usize, data: *mut c_void) -> c_int {
+		unsafe {
+			let info = &*info;
+			let (ptr, found) = &mut *(data as *mut (usize, Option<(usize, PathBuf)>));
+			let phdrs = std::slice::from_raw_parts(info.phdr, info.phnum.into());
+			let contains = phdrs.iter().any(|phdr| {
+				let start = info.addr.wrapping_add(phdr.vaddr as usize);
+				phdr.kind == PT_LOAD && (start..start + phdr.memsz as usize).contains(ptr)
+			});
+			if !contains {
+				return 0;
+			}
+			// The program itself has no name here
+			let name = CStr::from_ptr(info.name).to_bytes();
+			let path = if name.is_empty() {
+				Path::new("/proc/self/exe")
+			} else {
+				Path::new(OsStr::from_bytes(name))
+			};
+			*found = Some((info.addr, path.to_owned()));
+			1
+		}
+	}
+	let mut data = (ptr, None);
+	unsafe { dl_iterate_phdr(callback, &mut data as *mut _ as *mut c_void) };
+	data.1
+}
+
+/// Reads the functions with a size from the `.symtab` of `path`, at their addresses once loaded
+/// at `bias`.
+fn read_functions(path: &Path, bias: usize) -> Option<Functions> {
+	let file = File::open(path).ok()?;
+	let ehdr = read::<Ehdr>(&file, 0)?;
+	let class = if cfg!(target_pointer_width = "64") {
+		2
+	} else {
+		1
+	};
+	if ehdr.ident[..4] != *b"\x7FELF"
+		|| ehdr.ident[4] != class
+		|| usize::from(ehdr.shentsize) != size_of::<Shdr>()
+	{
+		return None;
+	}
+	let section = |i: usize| read::<Shdr>(&file, ehdr.shoff + i * size_of::<Shdr>());
+	let mut symtab = None;
+	for i in 0..usize::from(ehdr.shnum) {
+		let shdr = section(i)?;
+		if shdr.kind == SHT_SYMTAB {
+			symtab = Some(shdr);
+			break;
+		}
+	}
+	let symtab = symtab.filter(|symtab| symtab.entsize == size_of::<Sym>())?;
+	let strtab = section(symtab.link as usize)?;
+	let mut bytes = vec![0; symtab.size];
+	file.read_exact_at(&mut bytes, symtab.offset as u64).ok()?;
+	let mut names = vec![0; strtab.size];
+	file.read_exact_at(&mut names, strtab.offset as u64).ok()?;
+	let mut functions = bytes
+		.chunks_exact(size_of::<Sym>())
+		.map(|sym| unsafe { sym.as_ptr().cast::<Sym>().read_unaligned() })
+		.filter(|sym| sym.info & 0xF == STT_FUNC && sym.size != 0 && sym.shndx != 0)
+		.map(|sym| Function {
+			addr: bias.wrapping_add(sym.value as usize) & !THUMB,
+			size: sym.size as usize,
+			name: sym.name as usize,
+		})
+		.collect::<Vec<_>>();
+	functions.sort_unstable_by_key(|f| f.addr);
+	functions.dedup_by_key(|f| f.addr);
+	Some(Functions {
+		bias,
+		functions,
+		names,
+	})
+}
+
+/// Reads a `T` from `file` at `offset`.
+fn read<T>(file: &File, offset: usize) -> Option<T> {
+	let mut bytes = vec![0; size_of::<T>()];
+	file.read_exact_at(&mut bytes, offset as u64).ok()?;
+	Some(unsafe { bytes.as_ptr().cast::<T>().read_unaligned() })
+}
+
+#[test]
+fn symtab() {
+	// Not exported, so only in `.symtab`
+	#[inline(never)]
+	fn local(x: u32) -> u32 {
+		std::hint::black_box(x).wrapping_mul(3)
+	}
+	assert!(local(1) == 3);
+	let addr = local as *const u8 as usize;
+	assert!(symbol_size(addr).is_some_and(|size| size > 0));
+	assert_eq!(symbol_size(addr + 1), None);
+	#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+	{
+		assert!(is_part_of(addr + 1, addr));
+		assert!(!is_part_of(symtab as *const u8 as usize, addr));
+	}
+}
@@ -0,0 +1,173 @@
+use std::fmt;
+
+/// Wraps a `Formatter` to provide builders like `Formatter::debug_tuple` and
+/// `Formatter::debug_struct`, which are always printed on a single line, as with the patch.
+///
+/// This is for `Debug` implementations that want to be printed compactly regardless of whether
+/// the patch is enabled, without having to write out the punctuation by hand.
+///
+/// ```
+/// # use compact_debug::CompactFormatter;
+/// struct Goto(u32);
+/// impl std::fmt::Debug for Goto {
+///     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+///         CompactFormatter::new(f).debug_tuple("Goto").field(&self.0).finish()
+///     }
+/// }
+/// assert_eq!(format!("{:#?}", [Goto(30016)]), "[\n    Goto(30016),\n]");
+/// ```
+///
+/// The fields are printed with the same `Formatter`, so they are still printed with their usual
+/// `{:#?}` output.
+pub struct CompactFormatter<'a, 'b> {
+	f: &'a mut fmt::Formatter<'b>,
+}
+
+impl<'a, 'b> CompactFormatter<'a, 'b> {
+	/// Wraps `f`, which the builders write to.
+	pub fn new(f: &'a mut fmt::Formatter<'b>) -> Self {
+		CompactFormatter { f }
+	}
+
+	/// Like `Formatter::debug_tuple`.
+	pub fn debug_tuple(&mut self, name: &str) -> CompactDebugTuple<'_, 'b> {
+		let result = self.f.write_str(name);
+		CompactDebugTuple {
+			f: self.f,
+			result,
+			fields: 0,
+			empty_name: name.is_empty(),
+		}
+	}
+
+	/// Like `Formatter::debug_struct`.
+	pub fn debug_struct(&mut self, name: &str) -> CompactDebugStruct<'_, 'b> {
+		let result = self.f.write_str(name);
+		CompactDebugStruct {
+			f: self.f,
+			result,
+			has_fields: false,
+		}
+	}
+}
+
+/// Returned by [`CompactFormatter::debug_tuple`].
+#[must_use = "must eventually call `finish()` on Debug builders"]
+pub struct CompactDebugTuple<'a, 'b> {
+	f: &'a mut fmt::Formatter<'b>,
+	result: fmt::Result,
+	fields: usize,
+	empty_name: bool,
+}
+
+impl CompactDebugTuple<'_, '_> {
+	/// Like `DebugTuple::field`.
+	pub fn field(&mut self, value: &dyn fmt::Debug) -> &mut Self {
+		self.result = self.result.and_then(|()| {
+			self.f
+				.write_str(if self.fields == 0 { "(" } else { ", " })?;
+			value.fmt(self.f)
+		});
+		self.fields += 1;
+		self
+	}
+
+	/// Like `DebugTuple::finish`.
+	pub fn finish(&mut self) -> fmt::Result {
+		self.result.and_then(|()| {
+			if self.fields == 0 {
+				return Ok(());
+			}
+			// A tuple with a single element needs a trailing comma, as in `(1,)`
+			if self.fields == 1 && self.empty_name {
+				self.f.write_str(",")?;
+			}
+			self.f.write_str(")")
+		})
+	}
+
+	/// Like `DebugTuple::finish_non_exhaustive`.
+	pub fn finish_non_exhaustive(&mut self) -> fmt::Result {
+		self.result.and_then(|()| {
+			self.f
+				.write_str(if self.fields == 0 { "(..)" } else { ", ..)" })
+		})
+	}
+}
+
+/// Returned by [`CompactFormatter::debug_struct`].
+#[must_use = "must eventually call `finish()` on Debug builders"]
+pub struct CompactDebugStruct<'a, 'b> {
+	f: &'a mut fmt::Formatter<'b>,
+	result: fmt::Result,
+	has_fields: bool,
+}
+
+impl CompactDebugStruct<'_, '_> {
+	/// Like `DebugStruct::field`.
+	pub fn field(&mut self, name: &str, value: &dyn fmt::Debug) -> &mut Self {
+		self.result = self.result.and_then(|()| {
+			self.f
+				.write_str(if self.has_fields { ", " } else { " { " })?;
+			self.f.write_str(name)?;
+			self.f.write_str(": ")?;
+			value.fmt(self.f)
+		});
+		self.has_fields = true;
+		self
+	}
+
+	/// Like `DebugStruct::finish`.
+	pub fn finish(&mut self) -> fmt::Result {
+		self.result.and_then(|()| {
+			if self.has_fields {
+				self.f.write_str(" }")?;
+			}
+			Ok(())
+		})
+	}
+
+	/// Like `DebugStruct::finish_non_exhaustive`.
+	pub fn finish_non_exhaustive(&mut self) -> fmt::Result {
+		self.result.and_then(|()| {
+			self.f
+				.write_str(if self.has_fields { ", .. }" } else { " { .. }" })
+		})
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn builders() {
+	struct F(fn(&mut CompactFormatter) -> fmt::Result);
+	impl fmt::Debug for F {
+		fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+			(self.0)(&mut CompactFormatter::new(f))
+		}
+	}
+	let cases: [(F, &str); 8] = [
+		(F(|f| f.debug_tuple("A").finish()), "A"),
+		(F(|f| f.debug_tuple("").field(&1).finish()), "(1,)"),
+		(
+			F(|f| f.debug_tuple("A").field(&1).field(&[2]).finish()),
+			"A(1, [\n    2,\n])",
+		),
+		(F(|f| f.debug_tuple("A").finish_non_exhaustive()), "A(..)"),
+		(
+			F(|f| f.debug_tuple("A").field(&1).finish_non_exhaustive()),
+			"A(1, ..)",
+		),
+		(F(|f| f.debug_struct("B").finish()), "B"),
+		(
+			F(|f| f.debug_struct("B").field("x", &1).field("y", &2).finish()),
+			"B { x: 1, y: 2 }",
+		),
+		(
+			F(|f| f.debug_struct("B").field("x", &1).finish_non_exhaustive()),
+			"B { x: 1, .. }",
+		),
+	];
+	for (f, expected) in cases {
+		assert_eq!(format!("{f:#?}"), expected);
+	}
+}
@@ -0,0 +1,382 @@
+//! Replacing the functions that derived `Debug` implementations call for tuples, so that whether
+//! to print each one on a single line can be decided as it is printed.
+//!
+//! The functions are not patched but overwritten with a jump to a replacement. This is not a
+//! trampoline: the original is never called, so its first instructions are not relocated
+//! anywhere, only kept to be restored by [`unhook`]. Instead, the replacement does the same thing
+//! as the original, which only builds the tuple with `Formatter::debug_tuple`, or the same thing
+//! as the patch through [`CompactFormatter`].
+//!
+//! Jumping to the replacement is only sound because it is called exactly like the original, which
+//! relies on it having the same type as the method it replaces, with `self` as the first argument.
+
+use std::cell::Cell;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::patch::CompactDebugError;
+#[cfg(target_arch = "x86_64")]
+use crate::patch::{self, Builder};
+use crate::sync::RwLock;
+
+type Decide = dyn Fn(&str, &[&dyn Debug]) -> bool + Send + Sync;
+
+static DECIDE: RwLock<Option<Arc<Decide>>> = RwLock::new(None);
+
+type Filter = dyn Fn(&str) -> bool + Send + Sync;
+
+static FILTER: RwLock<Option<Arc<Filter>>> = RwLock::new(None);
+
+/// Counts the calls to [`unhook`], so that the flags in `THREAD` that were set before the last one
+/// are ignored on every thread.
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+	/// Set by [`enable_for_current_thread`], along with the `GENERATION` it was set in.
+	static THREAD: Cell<Option<(usize, bool)>> = const { Cell::new(None) };
+}
+
+/// Prints tuples in derived `Debug` implementations on a single line under `{:#?}` whenever
+/// `decide` returns true, given the name of the type or variant and its fields.
+///
+/// Unlike [`enable`](crate::enable), this can look at the values, for example to only put
+/// tuples on a single line if they are short:
+///
+/// ```no_run
+/// unsafe {
+///     compact_debug::hook(|name, fields| {
+///         name.len() + fields.iter().map(|v| format!("{v:?}").len()).sum::<usize>() < 40
+///     })
+/// }
+/// .expect("could not hook");
+/// ```
+///
+/// Calling this again replaces `decide`. This only affects the functions used by
+/// `#[derive(Debug)]`, so tuples printed with `Formatter::debug_tuple` directly, like the
+/// built-in tuples, are still printed as usual. The patch for tuples is disabled first, and
+/// enabling it while this is in effect fails with [`CompactDebugError::Hooked`]. If the functions
+/// cannot be overwritten, everything is left as it was, including the patch. Inside
+/// [`with_compact`](crate::with_compact), this fails with [`CompactDebugError::InsideCompact`].
+///
+/// This is only supported on x86_64; elsewhere, or under Miri, it does nothing.
+///
+/// # Safety
+/// See [`enable`](crate::enable). Additionally, `decide` must not call this or [`unhook`].
+pub unsafe fn hook(
+	decide: impl Fn(&str, &[&dyn Debug]) -> bool + Send + Sync + 'static,
+) -> Result<(), CompactDebugError> {
+	if !SUPPORTED {
+		return Ok(());
+	}
+	let previous = DECIDE.write().replace(Arc::new(decide));
+	let result = unsafe { install() };
+	if result.is_err() {
+		*DECIDE.write() = previous;
+	}
+	result
+}
+
+/// Prints tuples in derived `Debug` implementations on a single line under `{:#?}` if `on`, but
+/// only on the current thread, overriding whatever [`hook`] decides.
+///
+/// Other threads are unaffected, so for example tests running in parallel can each choose for
+/// themselves. The first call installs the same replacements as `hook`, and so has the same
+/// limitations; later calls only change a thread-local flag.
+///
+/// # Safety
+/// See [`hook`].
+pub unsafe fn enable_for_current_thread(on: bool) -> Result<(), CompactDebugError> {
+	if !SUPPORTED {
+		return Ok(());
+	}
+	let previous = THREAD.replace(Some((GENERATION.load(Ordering::Acquire), on)));
+	let result = unsafe { install() };
+	if result.is_err() {
+		THREAD.set(previous);
+	}
+	result
+}
+
+/// Only lets tuples be printed on a single line, by [`hook`] or [`enable_for_current_thread`], if
+/// `filter` returns true for the name of their type or variant.
+///
+/// This is for keeping some types as they are, such as errors with carefully laid out output:
+///
+/// ```
+/// compact_debug::set_filter(|name| !name.ends_with("Error"));
+/// ```
+///
+/// Only the name that the type passes to `Formatter::debug_tuple` is available, without its
+/// path or generic arguments. Calling this again replaces the filter, and [`unhook`] removes it.
+pub fn set_filter(filter: impl Fn(&str) -> bool + Send + Sync + 'static) {
+	*FILTER.write() = Some(Arc::new(filter));
+}
+
+/// Undoes [`hook`], [`enable_for_current_thread`] on every thread, and [`set_filter`], so that
+/// tuples are printed as usual again.
+///
+/// # Safety
+/// See [`enable`](crate::enable).
+pub unsafe fn unhook() -> Result<(), CompactDebugError> {
+	unsafe { uninstall() }?;
+	*DECIDE.write() = None;
+	*FILTER.write() = None;
+	GENERATION.fetch_add(1, Ordering::AcqRel);
+	Ok(())
+}
+
+const SUPPORTED: bool = cfg!(all(target_arch = "x86_64", not(miri)));
+
+#[cfg(not(target_arch = "x86_64"))]
+unsafe fn install() -> Result<(), CompactDebugError> {
+	Ok(())
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+unsafe fn uninstall() -> Result<(), CompactDebugError> {
+	Ok(())
+}
+
+/// Whether the functions are currently overwritten.
+#[cfg(not(target_arch = "x86_64"))]
+pub(crate) fn is_hooked() -> bool {
+	false
+}
+
+#[cfg(target_arch = "x86_64")]
+pub(crate) use x86_64::is_hooked;
+#[cfg(target_arch = "x86_64")]
+use x86_64::{install, uninstall};
+
+#[cfg(target_arch = "x86_64")]
+mod x86_64 {
+	use std::fmt::{self, Debug, Formatter};
+	use std::sync::atomic::Ordering;
+
+	use super::{patch, Builder, CompactDebugError, DECIDE, FILTER, GENERATION, THREAD};
+	use crate::sync::Mutex;
+	use crate::CompactFormatter;
+
+	/// The address and original bytes of each function that has been overwritten.
+	static HOOKED: Mutex<Vec<(usize, [u8; JUMP_LEN])>> = Mutex::new(Vec::new());
+
+	/// `jmp [rip]`, followed by the address to jump to.
+	const JUMP_LEN: usize = 14;
+
+	/// Overwrites the functions, unless that has already been done. If that fails, the functions
+	/// that were already overwritten are restored, and so is the patch for tuples.
+	///
+	/// The patch is not changed while this runs, so that it cannot be enabled in between being
+	/// disabled here and the functions being overwritten.
+	pub(super) unsafe fn install() -> Result<(), CompactDebugError> {
+		if patch::WRITING.get() {
+			return Ok(());
+		}
+		let _lock = (!patch::READING.get()).then(|| patch::SETTING.lock());
+		let was_enabled = patch::ENABLED[Builder::Tuple as usize].load(Ordering::Relaxed);
+		if was_enabled {
+			unsafe { patch::set_locked(Builder::Tuple, false) }?;
+		}
+		let result = unsafe { overwrite() };
+		// Only once the functions are restored, as it fails while they are overwritten
+		if result.is_err() && was_enabled {
+			let _ = unsafe { patch::set_locked(Builder::Tuple, true) };
+		}
+		result
+	}
+
+	unsafe fn overwrite() -> Result<(), CompactDebugError> {
+		let mut hooked = HOOKED.lock();
+		if !hooked.is_empty() {
+			return Ok(());
+		}
+		// Each replacement is coerced to the type of the method it replaces, so that they can only
+		// be paired up if they are called the same way
+		macro_rules! targets {
+			($($from:ident => $to:ident($($arg:ty),*),)*) => {
+				[$({
+					let from: fn(&mut Formatter<'static>, &str, $($arg),*) -> fmt::Result =
+						Formatter::$from;
+					let to: fn(&mut Formatter<'static>, &str, $($arg),*) -> fmt::Result = $to;
+					(
+						concat!("std::fmt::Formatter::", stringify!($from)),
+						from as *const u8 as usize,
+						to as *const u8 as usize,
+					)
+				}),*]
+			};
+		}
+		let targets = targets! {
+			debug_tuple_field1_finish => field1_finish(&dyn Debug),
+			debug_tuple_field2_finish => field2_finish(&dyn Debug, &dyn Debug),
+			debug_tuple_field3_finish => field3_finish(&dyn Debug, &dyn Debug, &dyn Debug),
+			debug_tuple_field4_finish => field4_finish(&dyn Debug, &dyn Debug, &dyn Debug, &dyn Debug),
+			debug_tuple_field5_finish => field5_finish(
+				&dyn Debug,
+				&dyn Debug,
+				&dyn Debug,
+				&dyn Debug,
+				&dyn Debug
+			),
+			debug_tuple_fields_finish => fields_finish(&[&dyn Debug]),
+		};
+		// The jump must not run into the next function
+		for (function, from, _) in targets {
+			if patch::symbol_size(from).is_some_and(|size| size < JUMP_LEN) {
+				return Err(CompactDebugError::PatternNotFound { function });
+			}
+		}
+		for (_, from, to) in targets {
+			match unsafe { write(from, jump(to)) } {
+				Ok(orig) => hooked.push((from, orig)),
+				Err(e) => {
+					let _ = unsafe { restore(&mut hooked) };
+					return Err(e);
+				}
+			}
+		}
+		Ok(())
+	}
+
+	pub(super) unsafe fn uninstall() -> Result<(), CompactDebugError> {
+		if patch::WRITING.get() {
+			return Ok(());
+		}
+		let _lock = (!patch::READING.get()).then(|| patch::SETTING.lock());
+		unsafe { restore(&mut HOOKED.lock()) }
+	}
+
+	/// Writes back the original bytes of the functions in `hooked`, removing each one once it is.
+	unsafe fn restore(hooked: &mut Vec<(usize, [u8; JUMP_LEN])>) -> Result<(), CompactDebugError> {
+		while let Some(&(from, orig)) = hooked.last() {
+			unsafe { write(from, orig) }?;
+			hooked.pop();
+		}
+		Ok(())
+	}
+
+	/// Whether the functions are currently overwritten.
+	pub(crate) fn is_hooked() -> bool {
+		!HOOKED.lock().is_empty()
+	}
+
+	fn jump(to: usize) -> [u8; JUMP_LEN] {
+		let mut code = [0; JUMP_LEN];
+		code[..6].copy_from_slice(&[0xFF, 0x25, 0, 0, 0, 0]);
+		code[6..].copy_from_slice(&to.to_le_bytes());
+		code
+	}
+
+	/// Writes `code` at `at`, returning what was there before.
+	unsafe fn write(at: usize, code: [u8; JUMP_LEN]) -> Result<[u8; JUMP_LEN], CompactDebugError> {
+		if patch::READING.get() {
+			return Err(CompactDebugError::InsideCompact);
+		}
+		let _lock = patch::PATCHING.write();
+		let ptr = at as *mut u8;
+		unsafe {
+			let regions = patch::protect(ptr, JUMP_LEN).map_err(CompactDebugError::Protect)?;
+			let orig = ptr.cast::<[u8; JUMP_LEN]>().read_unaligned();
+			let flushed = patch::parked(|| {
+				patch::with_write_access(|| ptr.cast::<[u8; JUMP_LEN]>().write_unaligned(code));
+				let flushed = patch::flush_icache(ptr, JUMP_LEN);
+				if flushed.is_err() {
+					patch::with_write_access(|| ptr.cast::<[u8; JUMP_LEN]>().write_unaligned(orig));
+					let _ = patch::flush_icache(ptr, JUMP_LEN);
+				}
+				std::sync::atomic::fence(Ordering::SeqCst);
+				flushed
+			});
+			let restored = patch::restore_protection(&regions).map_err(CompactDebugError::Protect);
+			flushed.and(restored)?;
+			Ok(orig)
+		}
+	}
+
+	/// What the replacements do, given the fields of the tuple.
+	fn finish(f: &mut Formatter, name: &str, fields: &[&dyn Debug]) -> fmt::Result {
+		let filter = FILTER.read().clone();
+		let thread = THREAD
+			.get()
+			.filter(|&(generation, _)| generation == GENERATION.load(Ordering::Acquire));
+		let compact = f.alternate()
+			&& filter.is_none_or(|filter| filter(name))
+			&& thread.map_or_else(
+				|| {
+					let decide = DECIDE.read().clone();
+					decide.is_some_and(|decide| decide(name, fields))
+				},
+				|(_, on)| on,
+			);
+		#[cfg(feature = "metrics")]
+		if f.alternate() {
+			crate::metrics::count(compact);
+			crate::metrics::flush();
+		}
+		if compact {
+			let mut f = CompactFormatter::new(f);
+			let mut builder = f.debug_tuple(name);
+			for field in fields {
+				builder.field(field);
+			}
+			builder.finish()
+		} else {
+			let mut builder = f.debug_tuple(name);
+			for field in fields {
+				builder.field(field);
+			}
+			builder.finish()
+		}
+	}
+
+	fn field1_finish(f: &mut Formatter, name: &str, value1: &dyn Debug) -> fmt::Result {
+		finish(f, name, &[value1])
+	}
+
+	fn field2_finish(
+		f: &mut Formatter,
+		name: &str,
+		value1: &dyn Debug,
+		value2: &dyn Debug,
+	) -> fmt::Result {
+		finish(f, name, &[value1, value2])
+	}
+
+	fn field3_finish(
+		f: &mut Formatter,
+		name: &str,
+		value1: &dyn Debug,
+		value2: &dyn Debug,
+		value3: &dyn Debug,
+	) -> fmt::Result {
+		finish(f, name, &[value1, value2, value3])
+	}
+
+	fn field4_finish(
+		f: &mut Formatter,
+		name: &str,
+		value1: &dyn Debug,
+		value2: &dyn Debug,
+		value3: &dyn Debug,
+		value4: &dyn Debug,
+	) -> fmt::Result {
+		finish(f, name, &[value1, value2, value3, value4])
+	}
+
+	fn field5_finish(
+		f: &mut Formatter,
+		name: &str,
+		value1: &dyn Debug,
+		value2: &dyn Debug,
+		value3: &dyn Debug,
+		value4: &dyn Debug,
+		value5: &dyn Debug,
+	) -> fmt::Result {
+		finish(f, name, &[value1, value2, value3, value4, value5])
+	}
+
+	fn fields_finish(f: &mut Formatter, name: &str, values: &[&dyn Debug]) -> fmt::Result {
+		finish(f, name, values)
+	}
+}
@@ -53,122 +53,116 @@
 //! ])), Address(30016)),
 //! ```
 //!
-//! This crate currently only supports x86_64 architecture.
-
-use std::sync::OnceLock;
-
-#[cfg(not(target_arch = "x86_64"))]
-compile_error!("only supported on x86_64");
-
-#[derive(Debug, Clone, Copy)]
-struct Position(usize, [u8; 2]);
-
-const POSITIONS: &[Position] = &[
-	Position(0x44, [0x75, 0x42]), // 2023-04-11, 2024-04-20, 2024-07-10, 1.79.0
-	Position(0x49, [0x75, 0x43]), // 2023-05-11
-	Position(0x46, [0x75, 0x3E]), // 2024-02-06, 1.77.2
-];
-
-const NOP: [u8; 2] = [0x66, 0x90];
-
-static WHICH: OnceLock<Position> = OnceLock::new();
-
-/// Enables or disables the patch.
-///
-/// # Panics
-/// Panics if the function does not look like expected, which is most likely to happen if `std`
-/// changes something internally, or if the compiler finds a better way to optimize it.
-///
-/// # Safety
-/// Aside from the whole concept being inherently unsafe, this will probably have unexpected
-/// consequences if called in multi-threaded contexts.
-pub unsafe fn enable(on: bool) {
-	unsafe {
-		let function = std::fmt::DebugTuple::field as *const () as *const u8;
-		let pos = WHICH.get_or_init(|| find(function));
-		let ptr = function.add(pos.0) as *mut [u8; 2];
-		if *ptr != pos.1 && *ptr != NOP {
-			panic!("DebugTuple::field is not as expected (is {:02X?})", *ptr);
-		}
-		let _prot =
-			region::protect_with_handle(ptr, 2, region::Protection::READ_WRITE_EXECUTE).unwrap();
-		ptr.write(if on { NOP } else { pos.1 });
-	}
-}
-
-unsafe fn find(ptr: *const u8) -> Position {
-	for &pos in POSITIONS {
-		let ptr = ptr.add(pos.0) as *const [u8; 2];
-		if *ptr == pos.1 {
-			return pos;
-		}
-	}
-
-	#[cfg(test)]
-	{
-		// If we couldn't find the position, try to guess one (but only print it, don't actually use it)
-		#[derive(Debug, Clone, Copy)]
-		enum State {
-			Start,
-			Ret,
-		}
-		let mut state = State::Start;
-		for i in 0..128 {
-			match (state, *ptr.add(i)) {
-				(State::Start, 0xC3) => state = State::Ret,
-				(State::Ret, 0x75) => panic!(
-					"could not find position - might be Position({:#02X}, [{:#02X}, {:#02X}])",
-					i,
-					*ptr.add(i),
-					*ptr.add(i + 1)
-				),
-				_ => (),
-			}
-		}
-
-		for i in 0..128 {
-			print!("{:02X} ", *ptr);
-			if i % 32 == 31 {
-				println!();
-			}
-		}
-	}
-
-	panic!("could not find position");
-}
-
-#[test]
-fn test() {
-	#[derive(Debug)]
-	#[allow(dead_code)]
-	struct A(u32, u32);
-
-	#[allow(dead_code)]
-	#[derive(Debug)]
-	struct B {
-		x: u32,
-		y: u32,
-	}
-
-	let a = A(8, 32);
-	let b = B { x: 8, y: 32 };
-
-	assert_eq!(format!("{a:?}"), "A(8, 32)");
-	assert_eq!(format!("{a:#?}"), "A(\n    8,\n    32,\n)");
-	assert_eq!(format!("{b:?}"), "B { x: 8, y: 32 }");
-	assert_eq!(format!("{b:#?}"), "B {\n    x: 8,\n    y: 32,\n}");
-
-	unsafe { enable(true) };
-
-	assert_eq!(format!("{a:?}"), "A(8, 32)");
-	assert_eq!(format!("{a:#?}"), "A(8, 32)");
-	assert_eq!(format!("{b:?}"), "B { x: 8, y: 32 }");
-	assert_eq!(format!("{b:#?}"), "B {\n    x: 8,\n    y: 32,\n}");
-
-	unsafe { enable(false) };
-
-	assert_eq!(format!("{a:?}"), "A(8, 32)");
-	assert_eq!(format!("{a:#?}"), "A(\n    8,\n    32,\n)");
-	assert_eq!(format!("{b:?}"), "B { x: 8, y: 32 }");
-	assert_eq!(format!("{b:#?}"), "B {\n    x: 8,\n    y: 32,\n}");
-}
+//! Structs, lists, sets and maps can be printed on a single line in the same way, with
+//! `enable_structs`, `enable_lists`, `enable_sets` and `enable_maps`. These are behind the `structs`,
+//! `lists`, `sets` and `maps` features respectively, while tuples are behind the default `tuples`
+//! feature.
+//!
+//! To avoid patching anything, a value can instead be wrapped in [`Compact`], which prints it the
+//! same way by reformatting its usual output. With the `macros` feature, types can also derive
+//! `CompactDebug` to print tuples on a single line by themselves. Separately, values of different
+//! types can be kept as `Box<dyn CompactDebug>` to print each of them like `Compact` does.
+//!
+//! For manual `Debug` implementations, [`CompactFormatter`] provides `debug_tuple` and `debug_struct`
+//! builders that are always printed on a single line.
+//!
+//! Since the patch affects all `{:#?}` output, it also affects the diffs printed by crates like
+//! `pretty_assertions`, which are more useful over several lines; `without_compact` disables it for
+//! the duration of a closure. For snapshot tests, `snapshot` formats a value with only the patch for
+//! tuples, whatever else is enabled at the time.
+//!
+//! With the `hook` feature on x86_64, `hook` instead decides whether to print each tuple on a
+//! single line as it is printed, such as only when it is short.
+//!
+//! With the `metrics` feature, `collapse_stats` counts how many values `Compact` and `hook` put on
+//! a single line and how many they left over several lines, to help choose a width for them.
+//!
+//! With the `auto` feature, the patch for tuples is enabled before `main` runs, without having to
+//! call `enable`.
+//!
+//! With the `stop-the-world` feature on Linux, the other threads are stopped while the patch is
+//! written, so that none of them runs an instruction that is only partly written.
+//!
+//! This crate currently only supports x86, x86_64, arm (Thumb-2), aarch64 and riscv64 architectures,
+//! and requires nightly. On other architectures it still compiles, but `enable` does nothing. The
+//! same goes for running under Miri, so that crates using this can still be tested with it.
+//!
+//! `no_std` crates are affected like any other, since the builders in `std::fmt` are those in
+//! `core::fmt`, as long as the program they are in links `std`. Targets without `std` are not
+//! supported, since changing the protection of memory, the locks and the thread-locals all rely on
+//! it.
+//!
+//! `std` can be linked either statically, as usual, or dynamically with `-C prefer-dynamic`, in which
+//! case this process's copy of its shared library is patched.
+//!
+//! Since the patch depends on exactly how `std` was compiled, building with a nightly it has not been
+//! tested with prints a warning, and `compatible` returns false. To see whether it works
+//! anyway, run `cargo run --example demo`.
+//!
+//! Where `offsets.txt` lists the sites for the compiler and target, those are checked instead of
+//! scanning the functions for them; `offset_table` prints the lines to add for the current ones.
+//!
+//! With the `cache` feature, the offsets that had to be scanned for are written to a file in the
+//! user's cache directory, or in `COMPACT_DEBUG_CACHE_DIR` if it is set, and later runs check them
+//! like those in `offsets.txt`, scanning again if they do not hold the pattern.
+//!
+//! The patch is behind the `patch` feature, which the features above enable. Without it, such as
+//! with `default-features = false`, only `Compact` and the derive are left, and the crate builds on
+//! stable.
+
+// The build script checks whether this is still needed
+#![cfg_attr(
+	all(feature = "patch", compact_debug_fmt_helpers_feature),
+	feature(fmt_helpers_for_derive)
+)]
+
+#[cfg(feature = "patch")]
+#[cfg_attr(any(target_arch = "x86", target_arch = "x86_64"), path = "arch/x86.rs")]
+#[cfg_attr(target_arch = "arm", path = "arch/arm.rs")]
+#[cfg_attr(target_arch = "aarch64", path = "arch/aarch64.rs")]
+#[cfg_attr(target_arch = "riscv64", path = "arch/riscv64.rs")]
+#[cfg_attr(
+	not(any(
+		target_arch = "x86",
+		target_arch = "x86_64",
+		target_arch = "arm",
+		target_arch = "aarch64",
+		target_arch = "riscv64",
+	)),
+	path = "arch/none.rs"
+)]
+mod arch;
+
+#[cfg(feature = "patch")]
+#[macro_use]
+mod patch;
+#[cfg(feature = "patch")]
+pub use patch::*;
+
+#[cfg(all(feature = "patch", target_os = "linux", target_env = "gnu"))]
+mod elf;
+#[cfg(all(feature = "stop-the-world", target_os = "linux"))]
+mod park;
+#[cfg(feature = "patch")]
+mod sync;
+
+// Without the functions that derived implementations call, there is nothing to hook
+#[cfg(all(feature = "hook", compact_debug_fmt_helpers))]
+mod hook;
+#[cfg(all(feature = "hook", compact_debug_fmt_helpers))]
+pub use hook::{enable_for_current_thread, hook, set_filter, unhook};
+
+mod formatter;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod wrapper;
+pub use formatter::{CompactDebugStruct, CompactDebugTuple, CompactFormatter};
+#[cfg(feature = "metrics")]
+pub use metrics::{collapse_stats, CollapseStats};
+pub use wrapper::{CollapseMode, Compact, CompactDebug, CompactWith, StructCollapse, Style};
+
+// Not while testing, where it would shadow `#[test]` for the tests in this crate
+#[cfg(all(feature = "macros", feature = "patch", not(test)))]
+pub use compact_debug_macros::test;
+#[cfg(feature = "macros")]
+pub use compact_debug_macros::CompactDebug;
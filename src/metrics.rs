@@ -0,0 +1,75 @@
+//! Counting how many groups [`Compact`](crate::Compact) and `hook` put on a single line, for
+//! tuning the widths and depths they are given.
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static SEEN: AtomicUsize = AtomicUsize::new(0);
+static COLLAPSED: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+	/// The groups counted by the value being printed, which a trial that does not fit puts back.
+	static PENDING: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
+/// How many groups have been printed, returned by [`collapse_stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct CollapseStats {
+	/// Every group that was printed under `{:#?}`.
+	pub seen: usize,
+	/// The groups that were put on a single line.
+	pub collapsed: usize,
+	/// The groups that were printed over several lines as usual.
+	pub expanded: usize,
+}
+
+/// Returns how many groups have been printed since the program started, and how many of them
+/// were put on a single line.
+///
+/// This counts the groups that [`Compact`](crate::Compact) reformats, and the tuples that `hook`
+/// decides on, in all threads. The patch puts everything on a single line without deciding
+/// anything, so it is not counted.
+///
+/// ```
+/// # use compact_debug::{collapse_stats, Compact};
+/// #[derive(Debug)]
+/// struct P {
+///     x: u32,
+/// }
+/// let before = collapse_stats();
+/// format!("{:#?}", Compact(P { x: 1 }));
+/// assert_eq!(collapse_stats().collapsed - before.collapsed, 1);
+/// ```
+pub fn collapse_stats() -> CollapseStats {
+	let seen = SEEN.load(Ordering::Relaxed);
+	let collapsed = COLLAPSED.load(Ordering::Relaxed).min(seen);
+	CollapseStats {
+		seen,
+		collapsed,
+		expanded: seen - collapsed,
+	}
+}
+
+/// Counts a group in the value being printed on this thread, until [`flush`].
+pub(crate) fn count(collapsed: bool) {
+	let (seen, n) = PENDING.get();
+	PENDING.set((seen + 1, n + usize::from(collapsed)));
+}
+
+/// What has been counted so far, to [`restore`] after a trial.
+pub(crate) fn save() -> (usize, usize) {
+	PENDING.get()
+}
+
+pub(crate) fn restore(saved: (usize, usize)) {
+	PENDING.set(saved);
+}
+
+/// Adds what has been counted on this thread to the totals.
+pub(crate) fn flush() {
+	let (seen, collapsed) = PENDING.replace((0, 0));
+	// Seen first, so that `collapse_stats` does not see more collapsed groups than were seen
+	SEEN.fetch_add(seen, Ordering::Relaxed);
+	COLLAPSED.fetch_add(collapsed, Ordering::Relaxed);
+}
@@ -0,0 +1,126 @@
+//! Stopping the other threads while the patch is written, so that none of them can run the code
+//! that is being changed and see half of an instruction.
+//!
+//! Every other thread is sent a signal, whose handler waits until the writing is done. The handler
+//! is installed the first time and never removed, since a thread that has the signal blocked for
+//! a while may only get it after the writing is done, and should then just carry on.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Once;
+use std::time::{Duration, Instant};
+
+use crate::sync::Mutex;
+
+/// How many threads have stopped since the signals were sent.
+static ARRIVED: AtomicUsize = AtomicUsize::new(0);
+/// How many of them have gone on again since.
+static LEFT: AtomicUsize = AtomicUsize::new(0);
+/// Whether the stopped threads may go on.
+static RELEASED: AtomicBool = AtomicBool::new(true);
+
+/// Held while the other threads are stopped, since two threads stopping each other at once would
+/// both wait in the handler forever.
+static PARKING: Mutex<()> = Mutex::new(());
+
+/// How long to wait for the other threads to stop, after which the patch is written anyway.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+extern "C" fn handler(_signal: libc::c_int) {
+	ARRIVED.fetch_add(1, Ordering::AcqRel);
+	while !RELEASED.load(Ordering::Acquire) {
+		unsafe { libc::sched_yield() };
+	}
+	LEFT.fetch_add(1, Ordering::AcqRel);
+}
+
+fn signal() -> libc::c_int {
+	libc::SIGRTMAX()
+}
+
+/// Runs `f` with every other thread in the process stopped.
+///
+/// `f` must not allocate or take any locks, since a stopped thread may be holding them.
+pub(crate) unsafe fn parked<R>(f: impl FnOnce() -> R) -> R {
+	static INSTALL: Once = Once::new();
+	INSTALL.call_once(|| unsafe {
+		let mut action = std::mem::zeroed::<libc::sigaction>();
+		action.sa_sigaction = handler as extern "C" fn(libc::c_int) as libc::sighandler_t;
+		action.sa_flags = libc::SA_RESTART;
+		libc::sigemptyset(&mut action.sa_mask);
+		libc::sigaction(signal(), &action, std::ptr::null_mut());
+	});
+	let _lock = PARKING.lock();
+	unsafe {
+		let pid = libc::getpid();
+		let me = libc::syscall(libc::SYS_gettid) as libc::pid_t;
+		let threads = std::fs::read_dir("/proc/self/task")
+			.into_iter()
+			.flatten()
+			.filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+			.filter(|&tid| tid != me)
+			.collect::<Vec<libc::pid_t>>();
+		ARRIVED.store(0, Ordering::Release);
+		LEFT.store(0, Ordering::Release);
+		RELEASED.store(false, Ordering::Release);
+		// Threads that have exited since they were listed cannot be sent anything
+		let sent = threads
+			.iter()
+			.filter(|&&tid| libc::syscall(libc::SYS_tgkill, pid, tid, signal()) == 0)
+			.count();
+		let deadline = Instant::now() + TIMEOUT;
+		while ARRIVED.load(Ordering::Acquire) < sent && Instant::now() < deadline {
+			libc::sched_yield();
+		}
+		let stopped = ARRIVED.load(Ordering::Acquire);
+		let result = {
+			let _release = Release;
+			f()
+		};
+		if stopped < sent {
+			log!(
+				warn,
+				"only {stopped} of {sent} threads stopped while the patch was written"
+			);
+		}
+		result
+	}
+}
+
+/// Lets the stopped threads go on when dropped, even if `f` panics.
+struct Release;
+
+impl Drop for Release {
+	fn drop(&mut self) {
+		RELEASED.store(true, Ordering::Release);
+		// Otherwise the next call could stop them again before they see that they may go on
+		while LEFT.load(Ordering::Acquire) < ARRIVED.load(Ordering::Acquire) {
+			unsafe { libc::sched_yield() };
+		}
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn parks() {
+	let running = AtomicBool::new(true);
+	let count = AtomicUsize::new(0);
+	std::thread::scope(|scope| {
+		scope.spawn(|| {
+			while running.load(Ordering::Relaxed) {
+				count.fetch_add(1, Ordering::Relaxed);
+			}
+		});
+		while count.load(Ordering::Relaxed) == 0 {
+			std::hint::spin_loop();
+		}
+		let during = unsafe {
+			parked(|| {
+				let before = count.load(Ordering::Relaxed);
+				std::thread::sleep(Duration::from_millis(20));
+				count.load(Ordering::Relaxed) - before
+			})
+		};
+		running.store(false, Ordering::Relaxed);
+		assert_eq!(during, 0);
+	});
+}
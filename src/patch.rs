@@ -0,0 +1,2280 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::{fmt, io};
+
+use crate::arch;
+use crate::sync::{Mutex, RwLock, RwLockReadGuard};
+
+/// Logs with the `log` crate, if the `log` feature is enabled.
+macro_rules! log {
+	($level:ident, $($arg:tt)*) => {{
+		#[cfg(feature = "log")]
+		::log::$level!($($arg)*);
+		// Still use the arguments, so that they do not become unused
+		#[cfg(not(feature = "log"))]
+		if false {
+			let _ = format_args!($($arg)*);
+		}
+	}};
+}
+
+/// The location of the `alternate` flag inside a `Formatter`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Flag {
+	pub(crate) offset: usize,
+	pub(crate) mask: u8,
+}
+
+/// A patch site, along with where it was found.
+#[derive(Debug, Clone, Copy)]
+struct Site {
+	function: &'static str,
+	start: usize,
+	pos: arch::Pos,
+}
+
+/// The `Debug` builders that can be patched separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Builder {
+	Tuple,
+	#[cfg(feature = "structs")]
+	Struct,
+	#[cfg(feature = "lists")]
+	List,
+	#[cfg(feature = "sets")]
+	Set,
+	#[cfg(feature = "maps")]
+	Map,
+}
+
+impl Builder {
+	const ALL: &[Builder] = &[
+		Builder::Tuple,
+		#[cfg(feature = "structs")]
+		Builder::Struct,
+		#[cfg(feature = "lists")]
+		Builder::List,
+		#[cfg(feature = "sets")]
+		Builder::Set,
+		#[cfg(feature = "maps")]
+		Builder::Map,
+	];
+}
+
+/// What was found for a builder.
+#[derive(Debug)]
+struct Matches {
+	sites: Vec<Site>,
+	/// The helpers for derived implementations in which nothing was found.
+	skipped: Vec<&'static str>,
+	/// How many sites were found in each function, in the order they were searched.
+	per_function: Vec<(&'static str, usize)>,
+}
+
+/// The functions left out of the search by [`Config::exclude`].
+static EXCLUDED: Mutex<Excluded> = Mutex::new(Excluded {
+	functions: &[],
+	searched: false,
+});
+
+struct Excluded {
+	functions: &'static [&'static str],
+	/// Whether anything has been searched with `functions`, after which they cannot change.
+	searched: bool,
+}
+
+/// The result of searching the functions of each builder. Failures are kept too, so that a failed
+/// search is not repeated by every later call.
+///
+/// Threads that need one at the same time before it is set wait for a single search, and all see
+/// its result.
+static MATCHES: [OnceLock<Result<Matches, SearchError>>; Builder::ALL.len()] =
+	[const { OnceLock::new() }; Builder::ALL.len()];
+
+/// Whether each builder is enabled. This is only changed after writing the sites, with release
+/// ordering, so that a thread that sees it set with acquire ordering also sees them written.
+pub(crate) static ENABLED: [AtomicBool; Builder::ALL.len()] =
+	[const { AtomicBool::new(false) }; Builder::ALL.len()];
+
+static SCAN_LIMIT: AtomicUsize = AtomicUsize::new(0x2000);
+
+/// Sets how many bytes to scan into a function whose size is unknown before giving up, which by
+/// default is 8 KiB.
+///
+/// Where the symbol table says how large the function is, that is used instead. Otherwise, the
+/// end of the function is guessed, and if the guess misses it, the scan would run into whatever
+/// follows; this limit turns that into an error.
+///
+/// This only has an effect before the first call to [`enable`].
+pub fn set_scan_limit(bytes: usize) {
+	SCAN_LIMIT.store(bytes, Ordering::Relaxed);
+}
+
+/// Why the patch could not be applied.
+#[derive(Debug)]
+pub enum CompactDebugError {
+	/// A function did not look like expected, which is most likely to happen if `std` changes
+	/// something internally, or if the compiler finds a better way to optimize it. Reports of this
+	/// should include [`std_build_info`].
+	PatternNotFound { function: &'static str },
+	/// Like `PatternNotFound`, but the function jumps elsewhere, to what may be a part of it that
+	/// was split off by PGO or BOLT, and nothing was found there either. Both addresses are given,
+	/// for looking at the code.
+	SplitPatternNotFound {
+		function: &'static str,
+		entry: usize,
+		fragment: usize,
+	},
+	/// The pointer to a function still had bits set above the address once any pointer
+	/// authentication code was stripped, as with a memory tag, so it could not be read from.
+	InvalidPointer { function: &'static str, ptr: usize },
+	/// The `alternate` flag could not be located inside `Formatter`.
+	OffsetMismatch,
+	/// The code could not be made writable.
+	Protect(region::Error),
+	/// The `COMPACT_DEBUG` environment variable was neither `1` nor `0`, for [`enable_from_env`].
+	InvalidEnv(std::ffi::OsString),
+	/// The patch was written, but [`verify_patch`] found that tuples were still printed over
+	/// several lines, so it was undone. This can happen if the functions that were patched are not
+	/// the ones that are called, such as when LTO duplicates them.
+	NoEffect,
+	/// The patch for tuples cannot be enabled while the functions it would write to are hooked,
+	/// with the `hook` feature.
+	Hooked,
+	/// [`Config::exclude`] changed which functions are excluded after the search had already
+	/// been done without them, so it would have had no effect.
+	ExcludedAfterSearch,
+	/// The patch cannot be changed inside [`with_compact`] or [`compact`], which would deadlock
+	/// otherwise.
+	InsideCompact,
+	/// `FlushInstructionCache` failed after writing the patch, on Windows, with the error from
+	/// `GetLastError`. The sites written by then are put back.
+	Flush(std::io::Error),
+}
+
+/// The errors that searching the functions can fail with, which unlike [`CompactDebugError`] can
+/// be copied out of the cached result of the search. Each is the variant of `CompactDebugError`
+/// with the same name.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum SearchError {
+	PatternNotFound {
+		function: &'static str,
+	},
+	// Only x86 follows jumps to fragments
+	#[cfg_attr(
+		not(any(target_arch = "x86", target_arch = "x86_64")),
+		allow(dead_code)
+	)]
+	SplitPatternNotFound {
+		function: &'static str,
+		entry: usize,
+		fragment: usize,
+	},
+	#[cfg(target_arch = "aarch64")]
+	InvalidPointer {
+		function: &'static str,
+		ptr: usize,
+	},
+	OffsetMismatch,
+}
+
+impl From<SearchError> for CompactDebugError {
+	fn from(e: SearchError) -> Self {
+		match e {
+			SearchError::PatternNotFound { function } => Self::PatternNotFound { function },
+			SearchError::SplitPatternNotFound {
+				function,
+				entry,
+				fragment,
+			} => Self::SplitPatternNotFound {
+				function,
+				entry,
+				fragment,
+			},
+			#[cfg(target_arch = "aarch64")]
+			SearchError::InvalidPointer { function, ptr } => Self::InvalidPointer { function, ptr },
+			SearchError::OffsetMismatch => Self::OffsetMismatch,
+		}
+	}
+}
+
+impl fmt::Display for CompactDebugError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::PatternNotFound { function } => {
+				write!(f, "no matches found in {function}{}", ToolchainHint)
+			}
+			Self::SplitPatternNotFound {
+				function,
+				entry,
+				fragment,
+			} => write!(
+				f,
+				"no matches found in {function} at {entry:#x}, nor where it jumps to at \
+				 {fragment:#x}{}",
+				ToolchainHint
+			),
+			Self::InvalidPointer { function, ptr } => {
+				write!(f, "the pointer to {function}, {ptr:#x}, is not an address")
+			}
+			Self::OffsetMismatch => write!(
+				f,
+				"could not find the alternate flag in Formatter{}",
+				ToolchainHint
+			),
+			Self::Protect(e) => write!(f, "could not make code writable: {e}{PROTECT_HINT}"),
+			Self::InvalidEnv(value) => write!(f, "COMPACT_DEBUG should be 1 or 0, not {value:?}"),
+			Self::NoEffect => write!(
+				f,
+				"the patch had no effect, so the patched functions may not be the ones that are \
+				 called{}",
+				ToolchainHint
+			),
+			Self::Hooked => write!(f, "the patch for tuples cannot be enabled while hooked"),
+			Self::ExcludedAfterSearch => write!(
+				f,
+				"the excluded functions cannot be changed after the functions have been searched"
+			),
+			Self::InsideCompact => write!(f, "the patch cannot be changed inside with_compact"),
+			Self::Flush(e) => write!(f, "could not flush the instruction cache: {e}"),
+		}
+	}
+}
+
+/// Suggests a toolchain mismatch in errors about the code looking unexpected, if the compiler is
+/// not one the patch was tested with.
+struct ToolchainHint;
+
+impl fmt::Display for ToolchainHint {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if compatible() {
+			return Ok(());
+		}
+		write!(
+			f,
+			" (this crate was built with {}, but has only been tested with nightlies from {} to {})",
+			env!("COMPACT_DEBUG_RUSTC_VERSION"),
+			env!("COMPACT_DEBUG_TESTED_FROM"),
+			env!("COMPACT_DEBUG_TESTED_TO"),
+		)
+	}
+}
+
+/// Returns whether the crate was built with a nightly that the patch is known to work with.
+///
+/// Since `std` is compiled into the program, this is the compiler the program was built with. If
+/// it is not, building the crate prints a warning, and [`enable`] may fail or, in the worst case,
+/// patch the wrong thing:
+///
+/// ```
+/// if compact_debug::compatible() {
+///     unsafe { compact_debug::enable(true) };
+/// }
+/// ```
+pub fn compatible() -> bool {
+	let date = env!("COMPACT_DEBUG_RUSTC_DATE");
+	(env!("COMPACT_DEBUG_TESTED_FROM")..=env!("COMPACT_DEBUG_TESTED_TO")).contains(&date)
+}
+
+/// Identifies the `std` that the patch applies to, for bug reports: the version of the compiler it
+/// came with, its commit hash, and the target, as in `rustc 1.97.0-nightly (e50aa6fba 2026-05-19),
+/// commit e50aa6fba4e63ab34c72bf9acfd2c307c1155d1a, x86_64-unknown-linux-gnu`.
+///
+/// The commit hash and the target are what `offsets.txt` is keyed on. With `-Zbuild-std`, `std`
+/// is compiled along with the program, so the same commit may give different code.
+pub fn std_build_info() -> &'static str {
+	concat!(
+		env!("COMPACT_DEBUG_RUSTC_VERSION"),
+		", commit ",
+		env!("COMPACT_DEBUG_RUSTC_COMMIT"),
+		", ",
+		env!("COMPACT_DEBUG_TARGET"),
+	)
+}
+
+impl std::error::Error for CompactDebugError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Self::Protect(e) => Some(e),
+			Self::Flush(e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+/// Enables or disables the patch.
+///
+/// On architectures that are not supported, under Miri, or without the `tuples` feature, this does
+/// nothing. On architectures other than x86 and x86_64, the instruction cache is flushed after
+/// writing.
+///
+/// # Panics
+/// Panics if [`try_enable`] fails.
+///
+/// While the sites are being written, such as from a panic hook that runs partway through, this
+/// does nothing on the same thread rather than write them again. Other threads can still run the
+/// code as it is being written, unless the `stop-the-world` feature stops them.
+///
+/// # Safety
+/// Aside from the whole concept being inherently unsafe, this will probably have unexpected
+/// consequences if called in multi-threaded contexts.
+pub unsafe fn enable(on: bool) {
+	unsafe { try_enable(on) }.unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// Like [`enable`], but returns an error instead of panicking, leaving formatting as it was.
+///
+/// This includes [`CompactDebugError::Protect`] when the code cannot be made writable, as in
+/// sandboxes that do not allow it, so that such programs can go on without the patch.
+///
+/// The functions are only searched once, so if that fails, every later call fails the same way.
+/// After enabling the patch, this checks that it works with [`verify_patch`], and undoes it if not.
+///
+/// # Safety
+/// See [`enable`].
+pub unsafe fn try_enable(on: bool) -> Result<(), CompactDebugError> {
+	unsafe { try_set(Builder::Tuple, on) }
+}
+
+/// Enables or disables the patch depending on whether the `COMPACT_DEBUG` environment variable
+/// is `1` or `0`, and does nothing if it is not set.
+///
+/// With the `auto` feature, this is what happens before `main` if the variable is set.
+///
+/// # Safety
+/// See [`enable`].
+pub unsafe fn enable_from_env() -> Result<(), CompactDebugError> {
+	match std::env::var_os("COMPACT_DEBUG") {
+		None => Ok(()),
+		Some(value) if value == "1" => unsafe { try_enable(true) },
+		Some(value) if value == "0" => unsafe { try_enable(false) },
+		Some(value) => Err(CompactDebugError::InvalidEnv(value)),
+	}
+}
+
+/// Like [`enable`], but for `DebugStruct`, so that `B { x: 8, y: 32 }` is also printed on a single
+/// line. This is independent of [`enable`].
+///
+/// # Panics
+/// See [`enable`].
+///
+/// # Safety
+/// See [`enable`].
+#[cfg(feature = "structs")]
+pub unsafe fn enable_structs(on: bool) {
+	unsafe { try_set(Builder::Struct, on) }.unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// Like [`enable`], but for `DebugList`, so that `[Var(0), Const(0), Op(Ne)]` is also printed on a
+/// single line. This is independent of [`enable`].
+///
+/// # Panics
+/// See [`enable`].
+///
+/// # Safety
+/// See [`enable`].
+#[cfg(feature = "lists")]
+pub unsafe fn enable_lists(on: bool) {
+	unsafe { try_set(Builder::List, on) }.unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// Like [`enable`], but for `DebugSet`, as used by `HashSet` and `BTreeSet`. This is independent
+/// of [`enable`].
+///
+/// `DebugSet` and `DebugList` usually share most of their code, so this may affect lists as well,
+/// and `enable_lists` may affect sets.
+///
+/// # Panics
+/// See [`enable`].
+///
+/// # Safety
+/// See [`enable`].
+#[cfg(feature = "sets")]
+pub unsafe fn enable_sets(on: bool) {
+	unsafe { try_set(Builder::Set, on) }.unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// Like [`enable`], but for `DebugMap`, as used by `HashMap` and `BTreeMap`. This is independent
+/// of [`enable`].
+///
+/// # Panics
+/// See [`enable`].
+///
+/// # Safety
+/// See [`enable`].
+#[cfg(feature = "maps")]
+pub unsafe fn enable_maps(on: bool) {
+	unsafe { try_set(Builder::Map, on) }.unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// Selects which builders to print on a single line, to enable all of them at once.
+///
+/// ```no_run
+/// # use compact_debug::Config;
+/// unsafe { Config::new().tuples(true).apply() }.expect("could not patch");
+/// ```
+///
+/// Only the builders whose features are enabled can be selected.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Config {
+	on: [bool; Builder::ALL.len()],
+	exclude: Option<&'static [&'static str]>,
+}
+
+impl Config {
+	/// Creates a configuration where no builders are selected.
+	pub fn new() -> Config {
+		Config::default()
+	}
+
+	fn set(mut self, builder: Builder, on: bool) -> Config {
+		self.on[builder as usize] = on;
+		self
+	}
+
+	/// Selects whether `DebugTuple` is printed on a single line, like [`enable`].
+	pub fn tuples(self, on: bool) -> Config {
+		self.set(Builder::Tuple, on)
+	}
+
+	/// Selects whether `DebugStruct` is printed on a single line, like `enable_structs`.
+	#[cfg(feature = "structs")]
+	pub fn structs(self, on: bool) -> Config {
+		self.set(Builder::Struct, on)
+	}
+
+	/// Selects whether `DebugList` is printed on a single line, like `enable_lists`.
+	#[cfg(feature = "lists")]
+	pub fn lists(self, on: bool) -> Config {
+		self.set(Builder::List, on)
+	}
+
+	/// Selects whether `DebugSet` is printed on a single line, like `enable_sets`.
+	#[cfg(feature = "sets")]
+	pub fn sets(self, on: bool) -> Config {
+		self.set(Builder::Set, on)
+	}
+
+	/// Selects whether `DebugMap` is printed on a single line, like `enable_maps`.
+	#[cfg(feature = "maps")]
+	pub fn maps(self, on: bool) -> Config {
+		self.set(Builder::Map, on)
+	}
+
+	/// Leaves out the given functions when searching for the patch sites, as if nothing was found
+	/// in them, for when the search goes wrong in one of them on a particular toolchain.
+	///
+	/// ```no_run
+	/// # use compact_debug::Config;
+	/// let config = Config::new().tuples(true).exclude(&["debug_tuple_fields_finish"]);
+	/// unsafe { config.apply() }.expect("could not patch");
+	/// ```
+	///
+	/// The names are either the full path, like `std::fmt::Formatter::debug_tuple_fields_finish`,
+	/// or the last part of it. Excluded functions are listed by [`skipped_functions`].
+	///
+	/// These replace the functions excluded by an earlier configuration, while one that does not
+	/// call this leaves them as they are. Since each builder is only searched once, they cannot be
+	/// changed after anything has been searched, by this or anything else, and `apply` fails with
+	/// [`CompactDebugError::ExcludedAfterSearch`] if they are.
+	pub fn exclude(mut self, functions: &'static [&'static str]) -> Config {
+		self.exclude = Some(functions);
+		self
+	}
+
+	/// Enables the patch for the selected builders, and disables it for the others.
+	///
+	/// The functions of all the selected builders are searched before anything is written, so if
+	/// that fails, nothing is changed. Builders that are not selected and not enabled are not
+	/// searched at all.
+	///
+	/// # Safety
+	/// See [`enable`].
+	pub unsafe fn apply(self) -> Result<(), CompactDebugError> {
+		if let Some(functions) = self.exclude {
+			let mut excluded = EXCLUDED.lock();
+			if excluded.functions != functions {
+				if excluded.searched {
+					return Err(CompactDebugError::ExcludedAfterSearch);
+				}
+				excluded.functions = functions;
+			}
+		}
+		for &builder in Builder::ALL {
+			if self.on[builder as usize] {
+				matches(builder)?;
+			}
+		}
+		for &builder in Builder::ALL {
+			let on = self.on[builder as usize];
+			if on || ENABLED[builder as usize].load(Ordering::Relaxed) {
+				unsafe { try_set(builder, on) }?;
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Enables or disables the patch for one builder.
+///
+/// Sites can be shared between builders if the linker merges identical functions, as tends to
+/// happen with `DebugList::entry` and `DebugSet::entry`. When disabling a builder, such sites are
+/// left alone if another builder that shares them is still enabled.
+///
+/// Only one thread does this at a time, as it would otherwise see another thread's changes
+/// halfway through.
+pub(crate) unsafe fn try_set(builder: Builder, on: bool) -> Result<(), CompactDebugError> {
+	if WRITING.get() {
+		log!(
+			warn,
+			"not changing {builder:?} while the patch is being written"
+		);
+		return Ok(());
+	}
+	// Inside `with_compact`, no other thread can write the sites anyway, and waiting for one that
+	// is about to would deadlock
+	let _lock = (!READING.get()).then(|| SETTING.lock());
+	unsafe { set_locked(builder, on) }
+}
+
+/// Like [`try_set`], for callers that already hold [`SETTING`].
+pub(crate) unsafe fn set_locked(builder: Builder, on: bool) -> Result<(), CompactDebugError> {
+	// Under the lock, so that the functions cannot be hooked between this and writing the sites
+	#[cfg(all(feature = "hook", compact_debug_fmt_helpers))]
+	if on && builder == Builder::Tuple && crate::hook::is_hooked() {
+		return Err(CompactDebugError::Hooked);
+	}
+	let mut matches = matches(builder)
+		.inspect_err(|e| log!(error, "could not find the patch sites for {builder:?}: {e}"))?
+		.to_vec();
+	let found = !matches.is_empty();
+	if !on {
+		let keep = in_use(Some(builder));
+		matches.retain(|site| !keep.contains(&site.pos.ptr()));
+	}
+	unsafe { patch(&matches, on) }
+		.inspect_err(|e| log!(error, "could not patch {builder:?}: {e}"))?;
+	if on && found && builder == Builder::Tuple && !verify_patch() {
+		log!(error, "the patch for {builder:?} had no effect");
+		unsafe { patch(&matches, false) }?;
+		return Err(CompactDebugError::NoEffect);
+	}
+	let was = ENABLED[builder as usize].swap(on && found, Ordering::AcqRel);
+	if was != (on && found) {
+		log!(
+			debug,
+			"{builder:?} is now {}",
+			if was { "disabled" } else { "enabled" }
+		);
+	}
+	Ok(())
+}
+
+/// The sites of the enabled builders other than `except`, which must stay written.
+fn in_use(except: Option<Builder>) -> Vec<*const u8> {
+	Builder::ALL
+		.iter()
+		.copied()
+		.filter(|&other| Some(other) != except && ENABLED[other as usize].load(Ordering::Relaxed))
+		.filter_map(|other| MATCHES[other as usize].get()?.as_ref().ok())
+		.flat_map(|matches| &matches.sites)
+		.map(|site| site.pos.ptr())
+		.collect()
+}
+
+/// Enables or disables the patch in the given functions, rather than in those of a builder.
+///
+/// This is for experimenting with builds of `std` that check the flag in other functions than
+/// usual. Each function is searched the same way as those of the builders, but on every call, and
+/// the sites found are written all at once, or not at all if the search fails in any of them. The
+/// names are only used in errors and logs.
+///
+/// ```no_run
+/// # use std::fmt::DebugTuple;
+/// unsafe {
+///     compact_debug::enable_functions(
+///         &[("std::fmt::DebugTuple::field", DebugTuple::field as *const u8)],
+///         true,
+///     )
+/// }
+/// .expect("could not patch");
+/// ```
+///
+/// This is not reflected by [`is_enabled`]. When disabling, the sites of any builder that is
+/// still enabled are left alone.
+///
+/// # Safety
+/// See [`enable`]. Additionally, each pointer must point to a function.
+pub unsafe fn enable_functions(
+	functions: &[(&'static str, *const u8)],
+	on: bool,
+) -> Result<(), CompactDebugError> {
+	if WRITING.get() {
+		log!(warn, "not changing the patch while it is being written");
+		return Ok(());
+	}
+	let _lock = (!READING.get()).then(|| SETTING.lock());
+	if cfg!(miri) {
+		return Ok(());
+	}
+	let flag = find_flag()?;
+	let mut sites = Vec::new();
+	for &(function, ptr) in functions {
+		let mut found = Vec::new();
+		let start = unsafe { arch::do_find(&mut found, function, strip(function, ptr)?, flag) }?;
+		sites.extend(found.into_iter().map(|pos| Site {
+			function,
+			start: start as usize,
+			pos,
+		}));
+	}
+	if !on {
+		let keep = in_use(None);
+		sites.retain(|site| !keep.contains(&site.pos.ptr()));
+	}
+	sites.sort_by_key(|site| site.pos.ptr());
+	sites.dedup_by_key(|site| site.pos.ptr());
+	unsafe { patch(&sites, on) }
+}
+
+/// Enables the patch for tuples for good, for programs that never disable it.
+///
+/// This searches the functions and checks the patch with [`verify_patch`] like [`try_enable`],
+/// but does not keep the sites it found, unless a search has been done already, so less is left
+/// around afterwards. Only the first successful call does anything; later calls return `Ok`
+/// straight away, even if the patch has been disabled in between.
+///
+/// # Safety
+/// See [`enable`].
+pub unsafe fn enable_once() -> Result<(), CompactDebugError> {
+	static DONE: AtomicBool = AtomicBool::new(false);
+	if DONE.load(Ordering::Acquire) || WRITING.get() {
+		return Ok(());
+	}
+	let _lock = (!READING.get()).then(|| SETTING.lock());
+	if DONE.load(Ordering::Acquire) {
+		return Ok(());
+	}
+	#[cfg(all(feature = "hook", compact_debug_fmt_helpers))]
+	if crate::hook::is_hooked() {
+		return Err(CompactDebugError::Hooked);
+	}
+	let found;
+	let sites = match MATCHES[Builder::Tuple as usize].get() {
+		Some(Ok(matches)) => &matches.sites,
+		_ => {
+			found = find_known(Builder::Tuple)?;
+			&found.sites
+		}
+	};
+	unsafe { patch(sites, true) }?;
+	if !sites.is_empty() && !verify_patch() {
+		unsafe { patch(sites, false) }?;
+		return Err(CompactDebugError::NoEffect);
+	}
+	ENABLED[Builder::Tuple as usize].store(!sites.is_empty(), Ordering::Release);
+	DONE.store(true, Ordering::Release);
+	Ok(())
+}
+
+/// Disables the patch; the same as `enable(false)`.
+///
+/// # Panics
+/// See [`enable`].
+///
+/// # Safety
+/// See [`enable`].
+pub unsafe fn disable() {
+	unsafe { enable(false) }
+}
+
+/// Enables the patch if it is disabled, and disables it if it is enabled.
+///
+/// Concurrent calls to `toggle`, [`push`] and [`pop`] are serialized, so two toggles always
+/// cancel out.
+///
+/// # Panics
+/// See [`enable`].
+///
+/// # Safety
+/// See [`enable`].
+pub unsafe fn toggle() {
+	let _lock = PUSHED.lock();
+	unsafe { enable(!is_enabled()) }
+}
+
+/// Returns the sites for `builder`; see [`search`].
+fn matches(builder: Builder) -> Result<&'static [Site], CompactDebugError> {
+	search(builder).map(|matches| &matches.sites[..])
+}
+
+/// Searches the functions the first time it is called, and returns the cached result.
+fn search(builder: Builder) -> Result<&'static Matches, CompactDebugError> {
+	match MATCHES[builder as usize].get_or_init(|| find_known(builder)) {
+		Ok(matches) => Ok(matches),
+		&Err(e) => Err(e.into()),
+	}
+}
+
+/// A place that [`enable`] writes to, as listed by [`patch_report`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PatchSite {
+	/// The function the site was found in, such as `std::fmt::DebugTuple::field`.
+	pub function: &'static str,
+	/// Where the code of the function starts, after following any jumps to it.
+	pub address: usize,
+	/// The offset of the patched bytes from `address`.
+	pub offset: usize,
+	/// What the patched bytes originally held: the immediate of the `test` instruction on x86, and
+	/// the branch instruction elsewhere.
+	pub original: u32,
+}
+
+/// Lists the places that [`enable`] would write to, without writing anything.
+///
+/// This searches the functions just like [`enable`] does, so it is useful for checking what the
+/// patch would do on a particular toolchain before enabling it. The functions that derived
+/// implementations call, in which nothing was found, are listed by [`skipped_functions`] instead.
+///
+/// # Panics
+/// Panics if the search fails; see [`try_enable`].
+pub fn patch_report() -> Vec<PatchSite> {
+	let matches = matches(Builder::Tuple).unwrap_or_else(|e| panic!("{e}"));
+	matches
+		.iter()
+		.map(|site| PatchSite {
+			function: site.function,
+			address: site.start,
+			offset: site.pos.ptr() as usize - site.start,
+			original: site.pos.orig(),
+		})
+		.collect()
+}
+
+/// Lists the functions like `Formatter::debug_tuple_field1_finish`, which derived implementations
+/// call, that nothing was found in, and so are not patched, along with any functions left out by
+/// [`Config::exclude`].
+///
+/// In some builds of `std`, these are optimized into little more than calls to the builders, so
+/// there is nothing to patch in them, and they are skipped rather than failing the search. If they
+/// do check the flag in a way that is not recognized, tuples printed through them are not
+/// affected by the patch.
+///
+/// # Panics
+/// See [`patch_report`].
+pub fn skipped_functions() -> Vec<&'static str> {
+	let matches = search(Builder::Tuple).unwrap_or_else(|e| panic!("{e}"));
+	matches.skipped.clone()
+}
+
+/// How the search for the patch sites went, as returned by [`discovery_stats`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DiscoveryStats {
+	/// How many sites were found in each function that was searched, in order. Sites that are
+	/// shared between functions are counted for each of them.
+	pub per_function: Vec<(&'static str, usize)>,
+}
+
+/// Returns how many sites were found in each function, for tuples.
+///
+/// When the patch does not work on a new nightly, this shows which function the search went wrong
+/// in, such as a helper with no sites while the others each have one, which is useful to include
+/// when reporting it.
+///
+/// # Panics
+/// See [`patch_report`].
+pub fn discovery_stats() -> DiscoveryStats {
+	let matches = search(Builder::Tuple).unwrap_or_else(|e| panic!("{e}"));
+	DiscoveryStats {
+		per_function: matches.per_function.clone(),
+	}
+}
+
+/// Checks that the patch actually works, by formatting some nested tuples with `{:#?}` and
+/// checking that they are printed on one line.
+///
+/// This returns `false` whenever the patch is disabled, so it is most useful right after enabling
+/// it.
+pub fn verify_patch() -> bool {
+	!format!("{:#?}", Some(Some((1, 2)))).contains('\n')
+}
+
+/// Returns the addresses of the bytes that [`enable`] writes to, in ascending order.
+///
+/// This searches the functions if that has not been done yet, and returns an empty slice if the
+/// search fails. See [`patch_report`] for more detail about each site.
+pub fn patched_sites() -> &'static [usize] {
+	static SITES: OnceLock<Vec<usize>> = OnceLock::new();
+	SITES.get_or_init(|| match matches(Builder::Tuple) {
+		Ok(matches) => matches.iter().map(|site| site.pos.ptr() as usize).collect(),
+		Err(_) => Vec::new(),
+	})
+}
+
+/// Returns whether the patch is currently enabled, that is, whether the last successful call to
+/// [`enable`] or [`try_enable`] enabled it, and [`restore_original`] has not been called since.
+///
+/// Where `enable` does nothing, such as under Miri, this is always `false`.
+///
+/// This only concerns tuples, not the builders enabled by `enable_structs` and the like.
+pub fn is_enabled() -> bool {
+	ENABLED[Builder::Tuple as usize].load(Ordering::Acquire)
+}
+
+/// Enables the patch before `main` runs, with the `auto` feature, or if `COMPACT_DEBUG` is set,
+/// does what [`enable_from_env`] says instead. If that fails, the error is printed to stderr and
+/// the program runs as usual.
+#[cfg(feature = "auto")]
+#[used]
+#[cfg_attr(
+	any(target_os = "linux", target_os = "android", target_os = "freebsd"),
+	link_section = ".init_array"
+)]
+#[cfg_attr(target_vendor = "apple", link_section = "__DATA,__mod_init_func")]
+#[cfg_attr(windows, link_section = ".CRT$XCU")]
+static AUTO: extern "C" fn() = {
+	extern "C" fn auto() {
+		let result = if std::env::var_os("COMPACT_DEBUG").is_some() {
+			unsafe { enable_from_env() }
+		} else {
+			unsafe { try_enable(true) }
+		};
+		if let Err(e) = result {
+			eprintln!("compact-debug: {e}");
+		}
+	}
+	auto
+};
+
+/// How many times [`push`] has been called without a matching [`pop`], and whether the patch
+/// was enabled before the outermost `push`. This is also locked by [`toggle`].
+static PUSHED: Mutex<(usize, bool)> = Mutex::new((0, false));
+
+/// Enables the patch until a matching call to [`pop`].
+///
+/// Unlike [`enable`], calls can be nested: the patch is only enabled by the outermost `push`, and
+/// put back the way it was by the outermost `pop`, so that independent parts of a program can each
+/// ask for it without turning it off for the others. Calling [`enable`] in between still takes
+/// effect immediately.
+///
+/// # Panics
+/// See [`enable`].
+///
+/// # Safety
+/// See [`enable`].
+pub unsafe fn push() {
+	let mut pushed = PUSHED.lock();
+	if pushed.0 == 0 {
+		pushed.1 = is_enabled();
+		unsafe { enable(true) };
+	}
+	pushed.0 += 1;
+}
+
+/// Undoes a call to [`push`]. If it was the outermost one, the patch is disabled, unless it was
+/// already enabled before that `push`.
+///
+/// # Panics
+/// Panics if there is no matching `push`. See also [`enable`].
+///
+/// # Safety
+/// See [`enable`].
+pub unsafe fn pop() {
+	let mut pushed = PUSHED.lock();
+	assert!(pushed.0 > 0, "pop without a matching push");
+	if pushed.0 == 1 {
+		unsafe { enable(pushed.1) };
+	}
+	pushed.0 -= 1;
+}
+
+/// Enables the patch until the returned guard is dropped, as with [`push`] and [`pop`].
+///
+/// Since the guard is dropped when unwinding, the patch is put back even if the code in between
+/// panics.
+///
+/// # Panics
+/// See [`enable`].
+///
+/// # Safety
+/// See [`enable`].
+pub unsafe fn scoped() -> CompactGuard {
+	unsafe { push() };
+	CompactGuard { _private: () }
+}
+
+/// Calls `f` with the patch enabled, putting it back the way it was afterwards, even if `f`
+/// panics. This is a function version of [`scoped!`](crate::scoped!), and nests the same way.
+///
+/// Other calls to `with_compact` and [`compact`] can run at the same time, and the patch stays
+/// enabled until all of them are done, but formatting anywhere else while the patch is being
+/// changed is still racy.
+///
+/// # Panics
+/// Panics if `f` tries to change the patch with [`enable`], which would deadlock otherwise;
+/// [`try_enable`] returns [`CompactDebugError::InsideCompact`] instead. See also [`enable`].
+///
+/// # Safety
+/// See [`enable`].
+pub unsafe fn with_compact<R>(f: impl FnOnce() -> R) -> R {
+	let _guard = unsafe { scoped() };
+	let _reading = Reading::new();
+	f()
+}
+
+/// Calls `f` with the patch disabled for every builder, putting it back the way it was
+/// afterwards, even if `f` panics. This is the opposite of [`with_compact`].
+///
+/// This is meant for code that compares `{:#?}` output, such as `assert_eq!` from
+/// `pretty_assertions`, whose diffs are much less useful when each value is on a single line:
+///
+/// ```no_run
+/// # let (left, right) = (Some(1), Some(1));
+/// unsafe { compact_debug::without_compact(|| assert_eq!(left, right)) };
+/// ```
+///
+/// The same goes for error reports that are laid out carefully over several lines, such as those
+/// printed by `anyhow` with `{:#?}`.
+///
+/// Calls to `with_compact` inside `f` still enable the patch. As with [`enable`], other threads
+/// that format anything while `f` runs are affected too.
+///
+/// # Panics
+/// See [`enable`].
+///
+/// # Safety
+/// See [`enable`].
+pub unsafe fn without_compact<R>(f: impl FnOnce() -> R) -> R {
+	struct Restore([bool; Builder::ALL.len()]);
+	impl Drop for Restore {
+		fn drop(&mut self) {
+			for &builder in Builder::ALL {
+				if self.0[builder as usize] {
+					// The caller of `without_compact` has already agreed to this
+					unsafe { try_set(builder, true) }.unwrap_or_else(|e| panic!("{e}"));
+				}
+			}
+		}
+	}
+	let restore = Restore(std::array::from_fn(|i| ENABLED[i].load(Ordering::Relaxed)));
+	for &builder in Builder::ALL {
+		if restore.0[builder as usize] {
+			unsafe { try_set(builder, false) }.unwrap_or_else(|e| panic!("{e}"));
+		}
+	}
+	f()
+}
+
+/// Held by [`try_set`] and [`restore_original`] while they change the patch, and by the `hook`
+/// feature while it overwrites the functions.
+pub(crate) static SETTING: Mutex<()> = Mutex::new(());
+
+/// Locked for writing while the patch sites are written, and for reading by [`with_compact`]
+/// while it runs, so that it never sees the patch half-written or disabled.
+pub(crate) static PATCHING: RwLock<()> = RwLock::new(());
+
+thread_local! {
+	/// Whether this thread holds a read lock on [`PATCHING`], which must not be taken again, as
+	/// a waiting writer would then deadlock it.
+	pub(crate) static READING: Cell<bool> = const { Cell::new(false) };
+
+	/// Whether this thread is writing the patch sites, in which case anything that would change
+	/// them is skipped.
+	pub(crate) static WRITING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// A read lock on [`PATCHING`], unless this thread already holds one.
+struct Reading(Option<RwLockReadGuard<'static, ()>>);
+
+impl Reading {
+	fn new() -> Self {
+		let outermost = !READING.replace(true);
+		Reading(outermost.then(|| PATCHING.read()))
+	}
+}
+
+impl Drop for Reading {
+	fn drop(&mut self) {
+		if self.0.is_some() {
+			READING.set(false);
+		}
+	}
+}
+
+/// Formats `value` with `{:#?}` with the patch enabled, and puts the patch back the way it was
+/// before returning.
+///
+/// As with [`with_compact`], concurrent calls always see the patch enabled.
+///
+/// # Panics
+/// See [`enable`].
+///
+/// # Safety
+/// See [`enable`].
+pub unsafe fn compact<T: fmt::Debug + ?Sized>(value: &T) -> String {
+	unsafe { compact_alternate(value) }
+}
+
+/// The same as [`compact`], for symmetry with [`compact_plain`].
+///
+/// Under `{:#?}`, the patched builders are printed on one line while the others, such as lists
+/// unless `enable_lists` is used, are still spread over several lines and indented.
+///
+/// # Panics
+/// See [`enable`].
+///
+/// # Safety
+/// See [`enable`].
+pub unsafe fn compact_alternate<T: fmt::Debug + ?Sized>(value: &T) -> String {
+	unsafe { with_compact(|| format!("{value:#?}")) }
+}
+
+/// Like [`compact`], but writes to `w` as it goes instead of collecting the output in a `String`,
+/// for large values.
+///
+/// # Panics
+/// See [`enable`].
+///
+/// # Safety
+/// See [`enable`].
+pub unsafe fn write_compact<W: io::Write + ?Sized, T: fmt::Debug + ?Sized>(
+	w: &mut W,
+	value: &T,
+) -> io::Result<()> {
+	unsafe { with_compact(|| write!(w, "{value:#?}")) }
+}
+
+/// Formats `value` with `{:?}`.
+///
+/// The patch only changes what `{:#?}` prints, and `{:?}` already prints everything on one line,
+/// so this does not need to touch the patch at all, and is the same as `format!("{value:?}")`. It
+/// differs from [`compact_alternate`] in that nothing is ever spread over several lines, but
+/// there is also no indentation for the builders that are not patched.
+pub fn compact_plain<T: fmt::Debug + ?Sized>(value: &T) -> String {
+	format!("{value:?}")
+}
+
+/// Formats `value` with `{:#?}` with only the patch for tuples enabled, regardless of what else has
+/// been enabled, so that the result is the same every time. This is meant for snapshot tests, such
+/// as with `insta`, which should not depend on which tests happened to run first:
+///
+/// ```ignore
+/// insta::assert_snapshot!(unsafe { compact_debug::snapshot(&ir) });
+/// ```
+///
+/// Unlike [`compact`], this disables `enable_structs` and the like while formatting, as with
+/// [`without_compact`]. It cannot be used while `hook` is in effect.
+///
+/// # Panics
+/// See [`enable`].
+///
+/// # Safety
+/// See [`enable`].
+pub unsafe fn snapshot<T: fmt::Debug + ?Sized>(value: &T) -> String {
+	unsafe { without_compact(|| compact(value)) }
+}
+
+/// Runs a block with the patch enabled, putting it back the way it was afterwards, as with
+/// [`scoped`](fn@scoped). The block's value is returned.
+///
+/// This holds a [`CompactGuard`] for the duration of the block, so the patch is also put back on
+/// early returns and panics.
+///
+/// ```no_run
+/// # #[derive(Debug)] struct Goto(u32);
+/// let text = unsafe { compact_debug::scoped! { format!("{:#?}", Goto(30016)) } };
+/// assert_eq!(text, "Goto(30016)");
+/// ```
+///
+/// # Safety
+/// This must be used inside an `unsafe` block; see [`enable`]. Since the patch affects the whole
+/// process, it is not limited to the block: other threads that format anything while the block
+/// runs get compact output too.
+#[macro_export]
+macro_rules! scoped {
+	($($body:tt)*) => {{
+		let _guard = $crate::scoped();
+		$($body)*
+	}};
+}
+
+/// Calls [`pop`] when dropped. See [`scoped`](fn@scoped).
+#[must_use = "the patch is disabled again as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct CompactGuard {
+	_private: (),
+}
+
+impl Drop for CompactGuard {
+	fn drop(&mut self) {
+		// The caller of `scoped` has already agreed to this
+		unsafe { pop() }
+	}
+}
+
+/// Enables the patch like [`enable`], returning a handle that writes back the bytes that were at
+/// each site before when dropped.
+///
+/// Unlike [`scoped`](fn@scoped), which only counts calls to itself, this puts back exactly what
+/// was there, whatever else was done to the patch in the meantime:
+///
+/// ```no_run
+/// let patched = unsafe { compact_debug::enable_patched() }.expect("could not patch");
+/// unsafe { compact_debug::enable(false) };
+/// drop(patched);
+/// ```
+///
+/// The handles are meant to be dropped in the opposite order they were created in, as with
+/// guards, since each puts back the state from when it was created.
+///
+/// If the bytes cannot be written back when the handle is dropped, such as when the code cannot
+/// be made writable, they are left as they are, and the error is logged with the `log` feature.
+///
+/// # Safety
+/// See [`enable`].
+pub unsafe fn enable_patched() -> Result<Patched, CompactDebugError> {
+	let snapshot = save(&[Builder::Tuple])?;
+	unsafe { try_set(Builder::Tuple, true) }?;
+	Ok(Patched(snapshot))
+}
+
+/// Returned by [`enable_patched`].
+#[must_use = "the patch is undone as soon as the handle is dropped"]
+#[derive(Debug)]
+pub struct Patched(ByteSnapshot);
+
+impl Drop for Patched {
+	fn drop(&mut self) {
+		// The caller of `enable_patched` has already agreed to this. Panicking here could abort
+		// while unwinding, so a failure is only logged
+		if let Err(e) = unsafe { restore_bytes(&self.0) } {
+			log!(error, "could not undo the patch: {e}");
+		}
+	}
+}
+
+/// The bytes at the patch sites, and which builders were enabled, as saved by [`byte_snapshot`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ByteSnapshot {
+	bytes: Vec<(usize, [u8; arch::LEN])>,
+	enabled: Vec<(Builder, bool)>,
+}
+
+/// Saves the bytes at the patch sites of every builder, for [`restore_bytes`] to write back.
+///
+/// This is more general than enabling and disabling the patch, since any number of states can be
+/// saved and put back in any order, such as to suspend it around code that expects the usual
+/// output and then resume whatever it was:
+///
+/// ```no_run
+/// # fn subsystem() {}
+/// let saved = compact_debug::byte_snapshot().expect("could not find the patch sites");
+/// unsafe { compact_debug::restore_original() };
+/// subsystem();
+/// unsafe { compact_debug::restore_bytes(&saved) }.expect("could not patch");
+/// ```
+///
+/// The functions of every builder whose feature is enabled are searched, unless that has been
+/// done already.
+pub fn byte_snapshot() -> Result<ByteSnapshot, CompactDebugError> {
+	save(Builder::ALL)
+}
+
+/// Writes back the bytes saved by [`byte_snapshot`], and marks the builders as enabled or
+/// disabled as they were then.
+///
+/// # Safety
+/// See [`enable`].
+pub unsafe fn restore_bytes(snapshot: &ByteSnapshot) -> Result<(), CompactDebugError> {
+	unsafe { write_bytes(&snapshot.bytes) }?;
+	for &(builder, on) in &snapshot.enabled {
+		ENABLED[builder as usize].store(on, Ordering::Release);
+	}
+	Ok(())
+}
+
+fn save(builders: &[Builder]) -> Result<ByteSnapshot, CompactDebugError> {
+	let mut bytes = Vec::new();
+	for &builder in builders {
+		for site in matches(builder)? {
+			let ptr = site.pos.ptr();
+			bytes.push((ptr as usize, unsafe {
+				ptr.cast::<[u8; arch::LEN]>().read_unaligned()
+			}));
+		}
+	}
+	bytes.sort_by_key(|&(addr, _)| addr);
+	bytes.dedup_by_key(|&mut (addr, _)| addr);
+	let enabled = builders
+		.iter()
+		.map(|&builder| (builder, ENABLED[builder as usize].load(Ordering::Acquire)))
+		.collect();
+	Ok(ByteSnapshot { bytes, enabled })
+}
+
+/// Writes the given bytes at each address that does not already hold them, which must be sorted
+/// by address, like [`patch`] does for the patch itself.
+unsafe fn write_bytes(saved: &[(usize, [u8; arch::LEN])]) -> Result<(), CompactDebugError> {
+	if WRITING.get() {
+		return Ok(());
+	}
+	let _setting = (!READING.get()).then(|| SETTING.lock());
+	let todo = saved
+		.iter()
+		.filter_map(|&(addr, bytes)| {
+			let orig = unsafe { (addr as *const [u8; arch::LEN]).read_unaligned() };
+			(orig != bytes).then_some((addr, bytes, orig))
+		})
+		.collect::<Vec<_>>();
+	let (Some(first), Some(last)) = (todo.first(), todo.last()) else {
+		return Ok(());
+	};
+	if READING.get() {
+		return Err(CompactDebugError::InsideCompact);
+	}
+	let len = last.0 + arch::LEN - first.0;
+	unsafe {
+		write_all(
+			&todo,
+			&|&(addr, bytes, orig), forward| {
+				let ptr = addr as *mut [u8; arch::LEN];
+				ptr.write_unaligned(if forward { bytes } else { orig });
+				ptr as *const u8
+			},
+			first.0 as *const u8,
+			len,
+		)
+	}
+}
+
+/// Writes back the original instructions at every patch site, as they were when first found.
+///
+/// Unlike `enable(false)`, this never searches the functions, so it does nothing if the patch has
+/// never been enabled or could not be, and calling it more than once is harmless.
+///
+/// # Panics
+/// Panics if the code cannot be made writable.
+///
+/// # Safety
+/// See [`enable`].
+pub unsafe fn restore_original() {
+	if WRITING.get() {
+		return;
+	}
+	let _lock = (!READING.get()).then(|| SETTING.lock());
+	for &builder in Builder::ALL {
+		if let Some(Ok(matches)) = MATCHES[builder as usize].get() {
+			unsafe { patch(&matches.sites, false) }.unwrap_or_else(|e| panic!("{e}"));
+			ENABLED[builder as usize].store(false, Ordering::Release);
+		}
+	}
+}
+
+/// Writes the patch sites that do not already hold the right bytes, under a single protection
+/// change spanning all of them, so that the protection is changed and restored only once. If all
+/// of them are already right, nothing is done at all.
+///
+/// Either all of the sites are written or none are: if writing panics or flushing the instruction
+/// cache fails partway through, the ones already written are put back as they were. If the
+/// protection cannot be restored afterwards, all of them have been written by then, and are left
+/// so.
+///
+/// `matches` must be sorted by address.
+unsafe fn patch(matches: &[Site], on: bool) -> Result<(), CompactDebugError> {
+	let todo = matches
+		.iter()
+		.map(|site| &site.pos)
+		.filter(|pos| unsafe { !pos.is_written(on) })
+		.collect::<Vec<_>>();
+	let (Some(first), Some(last)) = (todo.first(), todo.last()) else {
+		return Ok(());
+	};
+	if READING.get() {
+		return Err(CompactDebugError::InsideCompact);
+	}
+	let ptr = first.ptr();
+	let len = last.ptr() as usize + arch::LEN - ptr as usize;
+	unsafe {
+		write_all(
+			&todo,
+			&|pos, forward| {
+				pos.write(forward == on);
+				pos.ptr()
+			},
+			ptr,
+			len,
+		)
+	}
+}
+
+/// Writes each entry of `todo` with `write`, under a single protection change spanning
+/// `ptr..ptr + len`, and flushes the instruction cache after each one.
+///
+/// `write` writes an entry and returns where it is, or with `false`, puts back what the entry held
+/// before. If writing panics or flushing fails partway through, the entries already written are
+/// put back before returning or unwinding any further.
+unsafe fn write_all<T>(
+	todo: &[T],
+	write: &dyn Fn(&T, bool) -> *const u8,
+	ptr: *const u8,
+	len: usize,
+) -> Result<(), CompactDebugError> {
+	let _lock = PATCHING.write();
+	unsafe {
+		let regions = protect(ptr, len).map_err(CompactDebugError::Protect)?;
+		WRITING.set(true);
+		let mut writing = Writing {
+			todo,
+			write,
+			written: 0,
+			regions,
+		};
+		let flushed = parked(|| {
+			let flushed = writing.todo.iter().try_for_each(|entry| {
+				let ptr = with_write_access(|| (writing.write)(entry, true));
+				writing.written += 1;
+				flush_icache(ptr, arch::LEN)
+			});
+			// So that the writes are seen before anything that comes after, such as the flag in
+			// `ENABLED`, on architectures that reorder them
+			std::sync::atomic::fence(Ordering::SeqCst);
+			flushed
+		});
+		if let Err(e) = flushed {
+			writing.undo();
+			let _ = writing.finish();
+			return Err(e);
+		}
+		writing.finish()
+	}
+}
+
+/// The entries that [`write_all`] is writing, which are put back if it unwinds before
+/// [`finish`](Writing::finish), along with the protection of the pages they are on.
+struct Writing<'a, T> {
+	todo: &'a [T],
+	write: &'a dyn Fn(&T, bool) -> *const u8,
+	written: usize,
+	regions: Vec<(*const u8, usize, region::Protection)>,
+}
+
+impl<T> Writing<'_, T> {
+	unsafe fn finish(mut self) -> Result<(), CompactDebugError> {
+		let regions = std::mem::take(&mut self.regions);
+		unsafe { restore_protection(&regions) }.map_err(CompactDebugError::Protect)
+	}
+
+	/// Puts back the entries written so far.
+	unsafe fn undo(&mut self) {
+		unsafe {
+			parked(|| {
+				for entry in &self.todo[..self.written] {
+					let ptr = with_write_access(|| (self.write)(entry, false));
+					let _ = flush_icache(ptr, arch::LEN);
+				}
+			});
+		}
+		self.todo = &[];
+		self.written = 0;
+	}
+}
+
+impl<T> Drop for Writing<'_, T> {
+	fn drop(&mut self) {
+		unsafe {
+			if self.written < self.todo.len() {
+				self.undo();
+			}
+			let _ = restore_protection(&self.regions);
+		}
+		WRITING.set(false);
+	}
+}
+
+/// Makes the pages spanned by `ptr..ptr + len` writable, returning the previous protection of
+/// each part of them so that it can be restored afterwards.
+///
+/// Each part only has `WRITE` added to what it had, so that, for example, a page of data that
+/// the span ends on does not become executable while the patch is written.
+///
+/// This does not use `region::protect_with_handle`, which restores the whole of the first region
+/// rather than just the part that was changed, and so makes whatever follows it executable if the
+/// span crosses into another region.
+pub(crate) unsafe fn protect(
+	ptr: *const u8,
+	len: usize,
+) -> Result<Vec<(*const u8, usize, region::Protection)>, region::Error> {
+	let page = region::page::size();
+	let start = region::page::floor(ptr) as usize;
+	let end = (ptr as usize + len).next_multiple_of(page);
+	let regions = region::query_range(ptr, len)?
+		.map(|region| {
+			let region = region?;
+			let range = region.as_range();
+			let base = range.start.max(start);
+			Ok((
+				base as *const u8,
+				range.end.min(end) - base,
+				region.protection(),
+			))
+		})
+		.collect::<Result<Vec<_>, _>>()?;
+	for (i, &(base, size, protection)) in regions.iter().enumerate() {
+		if let Err(e) =
+			unsafe { region::protect(base, size, protection | region::Protection::WRITE) }
+		{
+			let _ = unsafe { restore_protection(&regions[..i]) };
+			return Err(e);
+		}
+	}
+	Ok(regions)
+}
+
+/// Puts back the protection returned by [`protect`]. All of the parts are restored even if one of
+/// them fails, so that as few pages as possible are left writable, and the first error is returned.
+pub(crate) unsafe fn restore_protection(
+	regions: &[(*const u8, usize, region::Protection)],
+) -> Result<(), region::Error> {
+	let mut result = Ok(());
+	for &(base, size, protection) in regions {
+		let restored = unsafe { region::protect(base, size, protection) };
+		result = result.and(restored);
+	}
+	result
+}
+
+#[cfg(all(feature = "stop-the-world", target_os = "linux"))]
+pub(crate) use crate::park::parked;
+
+/// Runs `f`, which writes the patch, while the other threads are stopped, with the
+/// `stop-the-world` feature on Linux.
+#[cfg(not(all(feature = "stop-the-world", target_os = "linux")))]
+pub(crate) unsafe fn parked<R>(f: impl FnOnce() -> R) -> R {
+	f()
+}
+
+#[cfg(target_os = "macos")]
+const PROTECT_HINT: &str = " (under the hardened runtime, this requires the \
+	com.apple.security.cs.allow-jit or com.apple.security.cs.disable-executable-page-protection \
+	entitlement)";
+#[cfg(not(target_os = "macos"))]
+const PROTECT_HINT: &str = "";
+
+/// Runs `f` with JIT write protection lifted for the current thread, which the hardened runtime
+/// on Apple Silicon otherwise enforces regardless of page protection.
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+pub(crate) unsafe fn with_write_access<R>(f: impl FnOnce() -> R) -> R {
+	unsafe extern "C" {
+		fn pthread_jit_write_protect_supported_np() -> i32;
+		fn pthread_jit_write_protect_np(enabled: i32);
+	}
+	unsafe {
+		let supported = pthread_jit_write_protect_supported_np() != 0;
+		if supported {
+			pthread_jit_write_protect_np(0);
+		}
+		let r = f();
+		if supported {
+			pthread_jit_write_protect_np(1);
+		}
+		r
+	}
+}
+
+#[cfg(not(all(target_os = "macos", target_arch = "aarch64")))]
+pub(crate) unsafe fn with_write_access<R>(f: impl FnOnce() -> R) -> R {
+	f()
+}
+
+/// Makes sure the written instructions are the ones that get executed.
+///
+/// This is architecture-specific, and does nothing on x86. Windows additionally wants
+/// `FlushInstructionCache` to be called after modifying code on every architecture.
+#[cfg(windows)]
+pub(crate) unsafe fn flush_icache(ptr: *const u8, len: usize) -> Result<(), CompactDebugError> {
+	use std::ffi::c_void;
+	#[link(name = "kernel32")]
+	unsafe extern "system" {
+		fn GetCurrentProcess() -> *mut c_void;
+		fn FlushInstructionCache(process: *mut c_void, base: *const c_void, size: usize) -> i32;
+	}
+	unsafe {
+		arch::flush_icache(ptr, len);
+		if FlushInstructionCache(GetCurrentProcess(), ptr as *const c_void, len) == 0 {
+			return Err(CompactDebugError::Flush(std::io::Error::last_os_error()));
+		}
+	}
+	Ok(())
+}
+
+#[cfg(not(windows))]
+pub(crate) unsafe fn flush_icache(ptr: *const u8, len: usize) -> Result<(), CompactDebugError> {
+	unsafe { arch::flush_icache(ptr, len) };
+	Ok(())
+}
+
+/// How far into a function to scan.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Bound {
+	pub(crate) end: usize,
+	/// Whether `end` is the actual end of the function, rather than a limit to give up at.
+	pub(crate) exact: bool,
+}
+
+impl Bound {
+	/// Looks up the size of `name`, which starts at `start`, falling back to the scan limit if the
+	/// symbol table does not have it, such as in a stripped binary. The limit is cut short where
+	/// the memory after the function stops being readable.
+	pub(crate) fn new(name: &'static str, start: *const u8) -> Bound {
+		let start = start as usize;
+		match symbol_size(start) {
+			Some(size) => Bound {
+				end: start + size,
+				exact: true,
+			},
+			None => {
+				let limit = SCAN_LIMIT.load(Ordering::Relaxed);
+				// Once, since this is likely to be the case for every function
+				static WARNED: AtomicBool = AtomicBool::new(false);
+				if !WARNED.swap(true, Ordering::Relaxed) {
+					log!(
+						warn,
+						"the size of {name} is not in the symbol table, so its end has to be \
+						guessed, and at most {limit} bytes are scanned; see set_scan_limit"
+					);
+				} else {
+					log!(debug, "the size of {name} is not in the symbol table");
+				}
+				Bound {
+					end: readable_end(start, start + limit),
+					exact: false,
+				}
+			}
+		}
+	}
+
+	/// Returns whether `ptr` is at the end of the function, or an error if it is at the limit.
+	// x86 and 32-bit Arm scan a slice instead
+	#[cfg_attr(
+		any(target_arch = "x86", target_arch = "x86_64", target_arch = "arm"),
+		allow(dead_code)
+	)]
+	pub(crate) fn reached<T>(
+		&self,
+		name: &'static str,
+		ptr: *const T,
+	) -> Result<bool, SearchError> {
+		let ptr = ptr as usize;
+		if ptr >= self.end && !self.exact {
+			return Err(SearchError::PatternNotFound { function: name });
+		}
+		Ok(ptr >= self.end)
+	}
+}
+
+/// Returns where the readable memory that starts at `start` ends, or `end` if it goes on that far.
+/// This is `start` if it is not readable at all.
+pub(crate) fn readable_end(start: usize, end: usize) -> usize {
+	let Ok(regions) = region::query_range(start as *const u8, end - start) else {
+		return start;
+	};
+	let mut readable = start;
+	for region in regions {
+		match region {
+			Ok(region)
+				if region.as_range().start <= readable
+					&& region.protection().contains(region::Protection::READ) =>
+			{
+				readable = region.as_range().end;
+			}
+			_ => break,
+		}
+		if readable >= end {
+			return end;
+		}
+	}
+	readable
+}
+
+/// Looks up the size of the function starting at `ptr` in the dynamic symbol table, or failing
+/// that in `.symtab`, if the binary has not been stripped of it. On 32-bit Arm, `ptr` is without
+/// the Thumb bit, which the symbols have set.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+pub(crate) fn symbol_size(ptr: usize) -> Option<usize> {
+	use std::ffi::{c_char, c_int, c_void};
+	#[repr(C)]
+	struct DlInfo {
+		fname: *const c_char,
+		fbase: *mut c_void,
+		sname: *const c_char,
+		saddr: *mut c_void,
+	}
+	const RTLD_DL_SYMENT: c_int = 1;
+	unsafe extern "C" {
+		fn dladdr1(
+			addr: *const c_void,
+			info: *mut DlInfo,
+			extra: *mut *mut c_void,
+			flags: c_int,
+		) -> c_int;
+	}
+	unsafe {
+		let mut info = std::mem::zeroed::<DlInfo>();
+		let mut sym = std::ptr::null_mut::<c_void>();
+		let ok = dladdr1(ptr as *const c_void, &mut info, &mut sym, RTLD_DL_SYMENT);
+		if ok == 0 {
+			return None;
+		}
+		let size = if sym.is_null() || info.saddr as usize & !crate::elf::THUMB != ptr {
+			0
+		} else {
+			(*(sym as *const crate::elf::Sym)).size as usize
+		};
+		// Most functions are not exported, so only the full symbol table has them
+		if size == 0 {
+			return crate::elf::symbol_size(ptr);
+		}
+		Some(size)
+	}
+}
+
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+pub(crate) fn symbol_size(_ptr: usize) -> Option<usize> {
+	None
+}
+
+/// Whether `target` is inside the function starting at `start`, or in a part of it that was split
+/// off, going by the symbol table. Without one, this is never known to be the case.
+#[cfg(all(
+	any(target_arch = "x86", target_arch = "x86_64"),
+	target_os = "linux",
+	target_env = "gnu"
+))]
+pub(crate) fn is_part_of(target: usize, start: usize) -> bool {
+	crate::elf::is_part_of(target, start)
+}
+
+#[cfg(all(
+	any(target_arch = "x86", target_arch = "x86_64"),
+	not(all(target_os = "linux", target_env = "gnu"))
+))]
+pub(crate) fn is_part_of(_target: usize, _start: usize) -> bool {
+	false
+}
+
+/// Finds which bit of `Formatter` is set by `#`, by formatting a probe value with and without it.
+fn find_flag() -> Result<Flag, SearchError> {
+	use std::cell::RefCell;
+	use std::fmt::{Debug, Formatter};
+	use std::mem::size_of;
+
+	struct Probe<'a>(&'a RefCell<Vec<Vec<u8>>>);
+	impl Debug for Probe<'_> {
+		fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+			let ptr = f as *const Formatter as *const u8;
+			let bytes = unsafe { std::slice::from_raw_parts(ptr, size_of::<Formatter>()) };
+			self.0.borrow_mut().push(bytes.to_vec());
+			Ok(())
+		}
+	}
+
+	let seen = RefCell::new(Vec::new());
+	let _ = format!("{:?}{:#?}", Probe(&seen), Probe(&seen));
+	let seen = seen.into_inner();
+	// Nothing in the search panics, so that the result is always kept in `MATCHES`
+	let [plain, alternate] = &seen[..] else {
+		return Err(SearchError::OffsetMismatch);
+	};
+
+	let mut diff = plain
+		.iter()
+		.zip(alternate)
+		.enumerate()
+		.filter(|(_, (a, b))| a != b);
+	let Some((offset, (a, b))) = diff.next() else {
+		return Err(SearchError::OffsetMismatch);
+	};
+	let mask = a ^ b;
+	if diff.next().is_some() || !mask.is_power_of_two() {
+		return Err(SearchError::OffsetMismatch);
+	}
+	Ok(Flag { offset, mask })
+}
+
+/// Strips any pointer authentication code from a function pointer, as on arm64e, so that it
+/// points to the actual instructions.
+///
+/// This uses `xpaclri`, which is a `nop` on cores without pointer authentication.
+#[cfg(target_arch = "aarch64")]
+fn strip(function: &'static str, ptr: *const u8) -> Result<*const u8, SearchError> {
+	let mut addr = ptr as usize;
+	unsafe {
+		std::arch::asm!("xpaclri", inout("x30") addr, options(nomem, nostack, preserves_flags));
+	}
+	if addr >> 48 != 0 {
+		return Err(SearchError::InvalidPointer {
+			function,
+			ptr: addr,
+		});
+	}
+	Ok(addr as *const u8)
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+fn strip(_function: &'static str, ptr: *const u8) -> Result<*const u8, SearchError> {
+	Ok(ptr)
+}
+
+/// Scans the functions for every builder again, without caching the result or using the known
+/// offsets, and returns how many sites were found. This is only meant for benchmarking the scan.
+#[doc(hidden)]
+pub fn find_uncached() -> Result<usize, CompactDebugError> {
+	let sites: Result<usize, SearchError> = Builder::ALL
+		.iter()
+		.map(|&builder| find_all(builder, &[], &[]).map(|matches| matches.sites.len()))
+		.sum();
+	Ok(sites?)
+}
+
+/// Searches the functions for every builder again, without caching the result, and returns the
+/// addresses of the sites found for each. With `known`, the known offsets are checked first,
+/// while otherwise every function is scanned. This is only meant for the tests.
+#[doc(hidden)]
+pub fn find_sites(known: bool) -> Result<Vec<Vec<usize>>, CompactDebugError> {
+	let known = if known { KNOWN_OFFSETS } else { &[] };
+	Builder::ALL
+		.iter()
+		.map(|&builder| {
+			let matches = find_all(builder, known, &[])?;
+			Ok(matches
+				.sites
+				.iter()
+				.map(|site| site.pos.ptr() as usize)
+				.collect())
+		})
+		.collect()
+}
+
+/// Calls [`try_enable`] as if from inside the write of the patch, as a panic hook that runs
+/// partway through it would. This is only meant for the tests.
+///
+/// # Safety
+/// See [`enable`].
+#[doc(hidden)]
+pub unsafe fn try_enable_while_writing(on: bool) -> Result<(), CompactDebugError> {
+	WRITING.set(true);
+	let result = unsafe { try_enable(on) };
+	WRITING.set(false);
+	result
+}
+
+/// Starts enabling the patch for tuples like [`enable`], but panics once the first site has been
+/// written, so that the tests can check that it is written back while unwinding.
+///
+/// # Safety
+/// See [`enable`].
+#[doc(hidden)]
+pub unsafe fn panic_while_writing() {
+	let _lock = SETTING.lock();
+	let sites = matches(Builder::Tuple).unwrap_or_else(|e| panic!("{e}"));
+	let todo = sites.iter().map(|site| &site.pos).collect::<Vec<_>>();
+	let (Some(&first), Some(&last)) = (todo.first(), todo.last()) else {
+		panic!("there are no sites to write");
+	};
+	let _patching = PATCHING.write();
+	unsafe {
+		let len = last.ptr() as usize + arch::LEN - first.ptr() as usize;
+		let regions = protect(first.ptr(), len).unwrap_or_else(|e| panic!("{e}"));
+		WRITING.set(true);
+		let mut writing = Writing {
+			todo: &todo,
+			write: &|pos, forward| {
+				pos.write(forward);
+				pos.ptr()
+			},
+			written: 0,
+			regions,
+		};
+		with_write_access(|| first.write(true));
+		writing.written += 1;
+		panic!("partway through");
+	}
+}
+
+/// Scans `code` for the flag test as if it were a function, where the flag is `mask` in the byte
+/// at `offset` into `Formatter`, and returns the offsets of the bytes the patch would write. This
+/// is only meant for fuzzing the scan, and only exists on x86 and x86_64.
+#[doc(hidden)]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn scan_code(
+	code: &[u8],
+	exact: bool,
+	offset: usize,
+	mask: u8,
+) -> Result<Vec<usize>, CompactDebugError> {
+	Ok(arch::scan_code(code, exact, Flag { offset, mask })?)
+}
+
+// The entries in `offsets.txt` for this compiler and target, as `KNOWN_OFFSETS`
+include!(concat!(env!("OUT_DIR"), "/offsets.rs"));
+
+/// Lists the sites that were found in every enabled builder, in the format of `offsets.txt`, so
+/// that they can be added there for the current compiler and target.
+///
+/// With an entry for a function there, that function is not scanned; the sites at the listed
+/// offsets are only checked to look right, and it is scanned as usual if they do not.
+///
+/// # Panics
+/// Panics if the search fails; see [`try_enable`].
+pub fn offset_table() -> String {
+	let mut sites = Builder::ALL
+		.iter()
+		.flat_map(|&builder| matches(builder).unwrap_or_else(|e| panic!("{e}")))
+		.collect::<Vec<_>>();
+	sites.sort_by_key(|site| (site.function, site.pos.ptr()));
+	sites.dedup_by_key(|site| (site.function, site.pos.ptr()));
+	offset_lines(&sites)
+}
+
+/// Formats `sites`, sorted by function, as lines of `offsets.txt`.
+fn offset_lines(sites: &[&Site]) -> String {
+	let mut out = String::new();
+	let mut function = "";
+	for site in sites {
+		if site.function != function {
+			function = site.function;
+			if !out.is_empty() {
+				out.push('\n');
+			}
+			out += &format!(
+				"{} {} {}",
+				env!("COMPACT_DEBUG_RUSTC_COMMIT"),
+				env!("COMPACT_DEBUG_TARGET"),
+				site.function
+			);
+		}
+		out += &format!(" {:#x}", site.pos.ptr() as usize - site.start);
+	}
+	if !out.is_empty() {
+		out.push('\n');
+	}
+	out
+}
+
+/// Searches the functions for `builder` with the offsets in `offsets.txt`, and with the `cache`
+/// feature those in the cache file, which is then updated with what was found.
+fn find_known(builder: Builder) -> Result<Matches, SearchError> {
+	let mut excluded = EXCLUDED.lock();
+	excluded.searched = true;
+	#[cfg(feature = "cache")]
+	let matches = {
+		let cached = cache::load();
+		let known = KNOWN_OFFSETS
+			.iter()
+			.copied()
+			.chain(cached.iter().map(|(f, offsets)| (f.as_str(), &offsets[..])))
+			.collect::<Vec<_>>();
+		let matches = find_all(builder, &known, excluded.functions)?;
+		// Excluded functions keep what was there
+		let searched = matches
+			.per_function
+			.iter()
+			.copied()
+			.filter(|(function, _)| !matches.skipped.contains(function))
+			.collect::<Vec<_>>();
+		cache::store(&cached, &searched, &matches.sites);
+		matches
+	};
+	#[cfg(not(feature = "cache"))]
+	let matches = find_all(builder, KNOWN_OFFSETS, excluded.functions)?;
+	Ok(matches)
+}
+
+/// Keeping the offsets that were found in a file, so that later runs only check them, as with
+/// `offsets.txt`, instead of scanning the functions again.
+#[cfg(feature = "cache")]
+mod cache {
+	use std::path::PathBuf;
+
+	use super::{offset_lines, Site};
+
+	/// The cache file for this compiler and target, in `COMPACT_DEBUG_CACHE_DIR` if it is set, and
+	/// otherwise in a `compact-debug` directory in the user's cache directory.
+	fn path() -> Option<PathBuf> {
+		let var = |name| {
+			std::env::var_os(name)
+				.filter(|v| !v.is_empty())
+				.map(PathBuf::from)
+		};
+		let dir = match var("COMPACT_DEBUG_CACHE_DIR") {
+			Some(dir) => dir,
+			None if cfg!(windows) => var("LOCALAPPDATA")?.join("compact-debug"),
+			None if cfg!(target_os = "macos") => var("HOME")?.join("Library/Caches/compact-debug"),
+			None => var("XDG_CACHE_HOME")
+				.filter(|dir| dir.is_absolute())
+				.or_else(|| Some(var("HOME")?.join(".cache")))?
+				.join("compact-debug"),
+		};
+		Some(dir.join(format!(
+			"{}-{}.txt",
+			env!("COMPACT_DEBUG_RUSTC_COMMIT"),
+			env!("COMPACT_DEBUG_TARGET")
+		)))
+	}
+
+	/// Reads the functions and offsets in the cache file, or nothing if there is none. Lines that
+	/// cannot be read, and those for another compiler or target, are left out.
+	pub(super) fn load() -> Vec<(String, Vec<usize>)> {
+		let Some(text) = path().and_then(|path| std::fs::read_to_string(path).ok()) else {
+			return Vec::new();
+		};
+		text.lines()
+			.filter_map(|line| {
+				let mut words = line.split_whitespace();
+				if words.next()? != env!("COMPACT_DEBUG_RUSTC_COMMIT")
+					|| words.next()? != env!("COMPACT_DEBUG_TARGET")
+				{
+					return None;
+				}
+				let function = words.next()?.to_owned();
+				let offsets = words
+					.map(|word| usize::from_str_radix(word.strip_prefix("0x")?, 16).ok())
+					.collect::<Option<_>>()?;
+				Some((function, offsets))
+			})
+			.collect()
+	}
+
+	/// Updates the cache file with the sites found in the functions that were searched, unless it
+	/// already holds them.
+	///
+	/// Failing to write it is only logged, since the sites are found either way.
+	pub(super) fn store(
+		cached: &[(String, Vec<usize>)],
+		searched: &[(&'static str, usize)],
+		sites: &[Site],
+	) {
+		let offsets = |function: &str| {
+			sites
+				.iter()
+				.filter(|site| site.function == function)
+				.map(|site| site.pos.ptr() as usize - site.start)
+				.collect::<Vec<_>>()
+		};
+		let unchanged = searched.iter().all(|&(function, _)| {
+			let old = cached.iter().find(|(f, _)| f == function);
+			old.map_or(&[][..], |(_, offsets)| offsets) == offsets(function)
+		});
+		let Some(path) = path().filter(|_| !unchanged) else {
+			return;
+		};
+		let mut sites = sites.iter().collect::<Vec<_>>();
+		sites.sort_by_key(|site| (site.function, site.pos.ptr()));
+		let mut text = offset_lines(&sites);
+		for (function, offsets) in cached {
+			if !searched.iter().any(|&(f, _)| f == function) {
+				text += &format!(
+					"{} {} {function}",
+					env!("COMPACT_DEBUG_RUSTC_COMMIT"),
+					env!("COMPACT_DEBUG_TARGET")
+				);
+				for offset in offsets {
+					text += &format!(" {offset:#x}");
+				}
+				text.push('\n');
+			}
+		}
+		// Written under another name first, so that other processes never read half of it
+		let temp = path.with_extension(format!("{}.tmp", std::process::id()));
+		let result = std::fs::create_dir_all(path.parent().unwrap())
+			.and_then(|()| std::fs::write(&temp, text))
+			.and_then(|()| std::fs::rename(&temp, &path));
+		match result {
+			Ok(()) => log!(debug, "wrote the offsets to {}", path.display()),
+			Err(e) => {
+				let _ = std::fs::remove_file(&temp);
+				log!(
+					warn,
+					"could not write the offsets to {}: {e}",
+					path.display()
+				);
+			}
+		}
+	}
+}
+
+/// Searches the functions for `builder`, except for those with an entry in `known` whose sites
+/// look right, and those in `excluded`, which are skipped.
+///
+/// The helpers that derived implementations call are skipped if nothing is found in them, since
+/// `std` may be optimized such that they only call the builders, which are searched anyway.
+fn find_all(
+	builder: Builder,
+	known: &[(&str, &[usize])],
+	excluded: &[&str],
+) -> Result<Matches, SearchError> {
+	// Miri cannot run code that has been changed at runtime, so act as if there was nothing to patch
+	if cfg!(miri) {
+		return Ok(Matches {
+			sites: Vec::new(),
+			skipped: Vec::new(),
+			per_function: Vec::new(),
+		});
+	}
+	let flag = find_flag()?;
+	let mut out = Vec::new();
+	let mut skipped = Vec::new();
+	let mut per_function = Vec::new();
+	macro_rules! find {
+		(helper $($t:tt)*) => { find!(@ true, $($t)*) };
+		(@ $helper:expr, $($t:tt)*) => {{
+			let function = stringify!($($t)*);
+			let mut found = Vec::new();
+			let exclude = excluded.iter().any(|&name| {
+				function.strip_suffix(name).is_some_and(|path| path.is_empty() || path.ends_with("::"))
+			});
+			let ptr = strip(function, $($t)* as *const u8)?;
+			let known = known.iter().filter(|_| !exclude).find(|&&(f, _)| f == function).and_then(|&(_, offsets)| {
+				let start = unsafe { arch::find_known(&mut found, function, ptr, flag, offsets) };
+				if start.is_none() {
+					log!(warn, "the known offsets in {function} do not hold the pattern, scanning it instead");
+				}
+				start
+			});
+			let start = match known {
+				_ if exclude => {
+					log!(info, "not searching {function}, which is excluded");
+					skipped.push(function);
+					None
+				}
+				Some(start) => Some(start),
+				None => match unsafe { arch::do_find(&mut found, function, ptr, flag) } {
+					Ok(start) => Some(start),
+					Err(
+						SearchError::PatternNotFound { .. }
+						| SearchError::SplitPatternNotFound { .. },
+					) if $helper => {
+						log!(warn, "no matches found in {function}, skipping it");
+						skipped.push(function);
+						None
+					}
+					Err(e) => return Err(e),
+				},
+			};
+			per_function.push((function, if start.is_some() { found.len() } else { 0 }));
+			if let Some(start) = start {
+				out.extend(found.into_iter().map(|pos| {
+					log!(debug, "found a site in {function} at {:?}, offset {:#x}, originally {:#x}",
+						pos.ptr(), pos.ptr() as usize - start as usize, pos.orig());
+					Site { function, start: start as usize, pos }
+				}));
+			}
+		}};
+		($($t:tt)*) => { find!(@ false, $($t)*) };
+	}
+	// The builders and `Formatter` in `std::fmt` are re-exports of those in `core::fmt`, so these
+	// are the functions in `core` that `no_std` code calls too, and there is no other copy of them
+	match builder {
+		Builder::Tuple if cfg!(feature = "tuples") => {
+			#[cfg(compact_debug_fmt_helpers)]
+			{
+				find!(helper std::fmt::Formatter::debug_tuple_field1_finish);
+				find!(helper std::fmt::Formatter::debug_tuple_field2_finish);
+				find!(helper std::fmt::Formatter::debug_tuple_field3_finish);
+				find!(helper std::fmt::Formatter::debug_tuple_field4_finish);
+				find!(helper std::fmt::Formatter::debug_tuple_field5_finish);
+				find!(helper std::fmt::Formatter::debug_tuple_fields_finish);
+			}
+			find!(std::fmt::DebugTuple::field);
+			find!(std::fmt::DebugTuple::finish);
+			find!(std::fmt::DebugTuple::finish_non_exhaustive);
+		}
+		#[cfg(feature = "structs")]
+		Builder::Struct => {
+			#[cfg(compact_debug_fmt_helpers)]
+			{
+				find!(helper std::fmt::Formatter::debug_struct_field1_finish);
+				find!(helper std::fmt::Formatter::debug_struct_field2_finish);
+				find!(helper std::fmt::Formatter::debug_struct_field3_finish);
+				find!(helper std::fmt::Formatter::debug_struct_field4_finish);
+				find!(helper std::fmt::Formatter::debug_struct_field5_finish);
+				find!(helper std::fmt::Formatter::debug_struct_fields_finish);
+			}
+			find!(std::fmt::DebugStruct::field);
+			find!(std::fmt::DebugStruct::finish);
+			find!(std::fmt::DebugStruct::finish_non_exhaustive);
+		}
+		// `finish` does not check the flag for these, since the entries all end in a newline
+		// anyway
+		#[cfg(feature = "lists")]
+		Builder::List => {
+			find!(std::fmt::DebugList::entry);
+			find!(std::fmt::DebugList::finish_non_exhaustive);
+		}
+		#[cfg(feature = "sets")]
+		Builder::Set => {
+			find!(std::fmt::DebugSet::entry);
+			find!(std::fmt::DebugSet::finish_non_exhaustive);
+		}
+		// Keys and values are written separately, and both need to agree on the layout
+		#[cfg(feature = "maps")]
+		Builder::Map => {
+			find!(std::fmt::DebugMap::entry);
+			find!(std::fmt::DebugMap::key);
+			find!(std::fmt::DebugMap::value);
+			find!(std::fmt::DebugMap::finish_non_exhaustive);
+		}
+		Builder::Tuple => {}
+	}
+	out.sort_by_key(|site| site.pos.ptr());
+	// A site is only found in two functions if they are the same one, merged by the linker, or
+	// if the scan of one ran past its end into the other, in which case the site belongs to the
+	// one that starts closer to it
+	out.dedup_by(|later, kept| {
+		if later.pos.ptr() != kept.pos.ptr() {
+			return false;
+		}
+		if later.start == kept.start {
+			log!(
+				debug,
+				"{} and {} share the site at {:?}, as they are the same function",
+				kept.function,
+				later.function,
+				kept.pos.ptr()
+			);
+			return true;
+		}
+		if later.start > kept.start {
+			std::mem::swap(later, kept);
+		}
+		log!(
+			warn,
+			"the scan of {} ran into {}, so the site at {:?} is only counted in the latter",
+			later.function,
+			kept.function,
+			kept.pos.ptr()
+		);
+		if let Some((_, count)) = per_function.iter_mut().find(|(f, _)| *f == later.function) {
+			*count -= 1;
+		}
+		true
+	});
+	Ok(Matches {
+		sites: out,
+		skipped,
+		per_function,
+	})
+}
+
+/// Discovery should succeed on whichever `std` the tests are linked against; build with
+/// `-Zbuild-std` and either profile to check both the debug and the release build.
+#[cfg(feature = "tuples")]
+#[test]
+fn find() {
+	let report = patch_report();
+	assert!(!report.is_empty());
+	let sites = report.iter().map(|site| site.address + site.offset);
+	assert!(sites.eq(patched_sites().iter().copied()));
+	assert!(skipped_functions().is_empty());
+	let stats = discovery_stats();
+	assert!(stats.per_function.iter().all(|&(_, found)| found != 0));
+	let total = stats
+		.per_function
+		.iter()
+		.map(|&(_, found)| found)
+		.sum::<usize>();
+	assert!(total >= report.len());
+}
+
+/// Every offset in `offsets.txt` for this compiler and target should be inside its function, as
+/// it is not if the scan that found it ran on into the next one.
+#[test]
+fn known_offsets_in_bounds() {
+	for &builder in Builder::ALL {
+		let Ok(matches) = find_all(builder, &[], &[]) else {
+			continue;
+		};
+		for &(function, offsets) in KNOWN_OFFSETS {
+			let Some(site) = matches.sites.iter().find(|site| site.function == function) else {
+				continue;
+			};
+			let Some(size) = symbol_size(site.start) else {
+				continue;
+			};
+			assert!(
+				offsets.iter().all(|&offset| offset < size),
+				"{function} is {size:#x} bytes long, but has offsets {offsets:#x?}"
+			);
+		}
+	}
+}
+
+/// Excluded functions should be skipped, whether named in full or not.
+#[cfg(feature = "tuples")]
+#[test]
+fn exclude() {
+	let all = find_all(Builder::Tuple, &[], &[]).unwrap();
+	let excluded = ["debug_tuple_fields_finish", "std::fmt::DebugTuple::finish"];
+	let matches = find_all(Builder::Tuple, &[], &excluded).unwrap();
+	assert_eq!(
+		matches.skipped,
+		[
+			#[cfg(compact_debug_fmt_helpers)]
+			"std::fmt::Formatter::debug_tuple_fields_finish",
+			"std::fmt::DebugTuple::finish",
+		]
+	);
+	assert!(matches.sites.len() < all.sites.len());
+	// Only whole parts of the path count, so the helpers ending in `_finish` are still searched
+	let matches = find_all(Builder::Tuple, &[], &["finish"]).unwrap();
+	assert_eq!(matches.skipped, ["std::fmt::DebugTuple::finish"]);
+}
+
+/// A write that starts at the end of one page must make the next one writable too, without
+/// giving either of them the other's protection.
+#[test]
+fn page_boundary() {
+	use region::Protection;
+	let page = region::page::size();
+	let memory = region::alloc(page * 2, Protection::READ).unwrap();
+	let first = memory.as_ptr::<u8>();
+	let second = first.wrapping_add(page);
+	unsafe { region::protect(second, page, Protection::READ_EXECUTE) }.unwrap();
+	let query = |ptr| region::query(ptr).unwrap().protection();
+
+	let last = first.wrapping_add(page - 1);
+	let regions = unsafe { protect(last, 2) }.unwrap();
+	assert_eq!(
+		(query(first), query(second)),
+		(Protection::READ_WRITE, Protection::READ_WRITE_EXECUTE)
+	);
+	unsafe { last.cast_mut().write_bytes(0x90, 2) };
+	for (base, size, protection) in regions {
+		unsafe { region::protect(base, size, protection) }.unwrap();
+	}
+	assert_eq!(
+		(query(first), query(second)),
+		(Protection::READ, Protection::READ_EXECUTE)
+	);
+	assert_eq!(unsafe { *second }, 0x90);
+}
+
+/// A scan without an exact size must stop before memory that cannot be read.
+#[test]
+fn readable() {
+	use region::Protection;
+	let page = region::page::size();
+	let memory = region::alloc(page * 3, Protection::READ).unwrap();
+	let start = memory.as_ptr::<u8>() as usize;
+	unsafe { region::protect((start + page) as *const u8, page, Protection::READ_EXECUTE) }
+		.unwrap();
+	unsafe { region::protect((start + page * 2) as *const u8, page, Protection::NONE) }.unwrap();
+	assert_eq!(readable_end(start, start + 10), start + 10);
+	assert_eq!(readable_end(start, start + page * 3), start + page * 2);
+	assert_eq!(
+		readable_end(start + page * 2, start + page * 3),
+		start + page * 2
+	);
+}
@@ -0,0 +1,50 @@
+//! The locks used by the patch, which are those from `parking_lot` with the `parking_lot` feature
+//! and otherwise wrap those from `std`.
+//!
+//! Either way there is no poisoning: a panic while one is held, such as in a `Debug`
+//! implementation inside `with_compact`, does nothing worse than release it.
+
+#[cfg(feature = "parking_lot")]
+pub(crate) use parking_lot::{Mutex, RwLock, RwLockReadGuard};
+
+#[cfg(not(feature = "parking_lot"))]
+pub(crate) use self::std_sync::{Mutex, RwLock, RwLockReadGuard};
+
+#[cfg(not(feature = "parking_lot"))]
+mod std_sync {
+	use std::sync::{self, MutexGuard, PoisonError, RwLockWriteGuard};
+
+	pub(crate) use std::sync::RwLockReadGuard;
+
+	pub(crate) struct Mutex<T: ?Sized>(sync::Mutex<T>);
+
+	impl<T> Mutex<T> {
+		pub(crate) const fn new(value: T) -> Self {
+			Mutex(sync::Mutex::new(value))
+		}
+	}
+
+	impl<T: ?Sized> Mutex<T> {
+		pub(crate) fn lock(&self) -> MutexGuard<'_, T> {
+			self.0.lock().unwrap_or_else(PoisonError::into_inner)
+		}
+	}
+
+	pub(crate) struct RwLock<T: ?Sized>(sync::RwLock<T>);
+
+	impl<T> RwLock<T> {
+		pub(crate) const fn new(value: T) -> Self {
+			RwLock(sync::RwLock::new(value))
+		}
+	}
+
+	impl<T: ?Sized> RwLock<T> {
+		pub(crate) fn read(&self) -> RwLockReadGuard<'_, T> {
+			self.0.read().unwrap_or_else(PoisonError::into_inner)
+		}
+
+		pub(crate) fn write(&self) -> RwLockWriteGuard<'_, T> {
+			self.0.write().unwrap_or_else(PoisonError::into_inner)
+		}
+	}
+}
@@ -0,0 +1,604 @@
+use std::fmt;
+
+/// Prints the wrapped value with tuples and structs on a single line under `{:#?}`, like
+/// `enable` and `enable_structs` together, but without patching anything.
+///
+/// This works by parsing the value's normal `{:#?}` output and printing it again, so it is slower
+/// than the patch, and a `Debug` implementation that prints something other than what the
+/// builders print, such as a string with unbalanced brackets, can confuse it. In that case the
+/// output is left as it is.
+///
+/// ```
+/// # use compact_debug::Compact;
+/// #[derive(Debug)]
+/// struct Address(u32);
+/// let text = format!("{:#?}", Compact(vec![Some(Address(30016))]));
+/// assert_eq!(text, "[\n    Some(Address(30016)),\n]");
+/// ```
+///
+/// Wrapped values are reformatted as a whole, so values nested inside them, such as the fields of
+/// a tuple, are printed compactly as well, without having to be wrapped themselves. By default
+/// only tuples and structs are put on a single line, while lists, maps and sets stay as they are,
+/// as with the patch. Unlike with the patch, values wider than 100 columns are not put on a single
+/// line either. Use [`Compact::with`] and a [`Style`] to change this.
+///
+/// Values without any fields are printed as `std` prints them, which for tuples and structs is
+/// just the name, as in `B` for `B {}`. A `Debug` implementation that spreads an empty struct over
+/// several lines by itself gets `B {}`, without the spaces that separate fields from the brackets.
+///
+/// With `{:?}`, this prints the same as the wrapped value.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Compact<T>(pub T);
+
+impl<T> Compact<T> {
+	/// Wraps a value to be printed with the given style, rather than the default one.
+	///
+	/// ```
+	/// # use compact_debug::{Compact, Style};
+	/// let text = format!("{:#?}", Compact::with(Some(vec![1, 2]), Style::new().lists(true)));
+	/// assert_eq!(text, "Some([1, 2])");
+	/// ```
+	pub fn with(value: T, style: Style) -> CompactWith<T> {
+		CompactWith { value, style }
+	}
+
+	/// Wraps a value to be printed with the default style, but with a different
+	/// [`max_width`](Style::max_width).
+	pub fn with_width(value: T, width: usize) -> CompactWith<T> {
+		Compact::with(value, Style::new().max_width(width))
+	}
+
+	/// Wraps a value to be printed with the default style, but with a
+	/// [`max_depth`](Style::max_depth).
+	pub fn with_depth(value: T, depth: usize) -> CompactWith<T> {
+		Compact::with(value, Style::new().max_depth(depth))
+	}
+}
+
+impl<T: fmt::Debug> fmt::Debug for Compact<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		Compact::with(&self.0, Style::new()).fmt(f)
+	}
+}
+
+/// Prints values like [`Compact`] does, through a trait object.
+///
+/// This is implemented for every `Debug` type, and `dyn CompactDebug` implements `Debug` by
+/// calling [`compact_fmt`](CompactDebug::compact_fmt), so values of different types can be kept
+/// together and printed compactly without the patch:
+///
+/// ```
+/// # use compact_debug::CompactDebug;
+/// let values: Vec<Box<dyn CompactDebug>> = vec![Box::new(Some(1)), Box::new((2, "3"))];
+/// assert_eq!(format!("{values:#?}"), "[\n    Some(1),\n    (2, \"3\"),\n]");
+/// ```
+///
+/// This is unrelated to the derive macro of the same name, which lives in a different namespace.
+pub trait CompactDebug {
+	/// Prints the value like `Compact(self)` would.
+	fn compact_fmt(&self, f: &mut fmt::Formatter) -> fmt::Result;
+}
+
+impl<T: fmt::Debug + ?Sized> CompactDebug for T {
+	fn compact_fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt::Debug::fmt(&Compact(self), f)
+	}
+}
+
+impl fmt::Debug for dyn CompactDebug + '_ {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.compact_fmt(f)
+	}
+}
+
+/// Which kinds of values [`Compact::with`] puts on a single line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Style {
+	lists: bool,
+	maps: bool,
+	max_width: usize,
+	max_line: usize,
+	widths: [Option<usize>; 4],
+	max_fields: usize,
+	max_depth: usize,
+	mode: CollapseMode,
+	structs: StructCollapse,
+	indent: &'static str,
+	comma_space: bool,
+}
+
+impl Style {
+	/// The style used by [`Compact`], where tuples and structs are put on a single line if they
+	/// fit in 100 columns.
+	pub const fn new() -> Style {
+		Style {
+			lists: false,
+			maps: false,
+			max_width: 100,
+			max_line: 2000,
+			widths: [None; 4],
+			max_fields: usize::MAX,
+			max_depth: usize::MAX,
+			mode: CollapseMode::All,
+			structs: StructCollapse::All,
+			indent: "    ",
+			comma_space: true,
+		}
+	}
+
+	/// Whether to put lists on a single line, like `enable_lists`.
+	pub const fn lists(mut self, on: bool) -> Style {
+		self.lists = on;
+		self
+	}
+
+	/// Whether to put maps and sets on a single line, like `enable_maps` and `enable_sets`. The
+	/// two cannot be told apart in the output, so this applies to both.
+	pub const fn maps(mut self, on: bool) -> Style {
+		self.maps = on;
+		self
+	}
+
+	/// The number of columns that a value put on a single line may take up, 100 by default.
+	///
+	/// Values that would be wider than this are printed over several lines as usual, while
+	/// the values inside them may still fit on a single line each. Values containing anything
+	/// that is still printed over several lines, such as a list, are tried out first, and are
+	/// only put on a single line if every line fits.
+	pub const fn max_width(mut self, width: usize) -> Style {
+		self.max_width = width;
+		self
+	}
+
+	/// The number of columns that no line put together from values on a single line may exceed,
+	/// 2000 by default, whatever [`max_width`](Style::max_width) and the widths for each kind say.
+	///
+	/// This is a last resort against lines long enough to make a terminal struggle, for when the
+	/// widths are set to `usize::MAX` to put everything on a single line.
+	pub const fn max_line(mut self, width: usize) -> Style {
+		self.max_line = width;
+		self
+	}
+
+	/// Like [`max_width`](Style::max_width), but only for tuples, which otherwise use that.
+	pub const fn tuple_width(mut self, width: usize) -> Style {
+		self.widths[Kind::Tuple as usize] = Some(width);
+		self
+	}
+
+	/// Like [`max_width`](Style::max_width), but only for structs, which otherwise use that.
+	pub const fn struct_width(mut self, width: usize) -> Style {
+		self.widths[Kind::Struct as usize] = Some(width);
+		self
+	}
+
+	/// Like [`max_width`](Style::max_width), but only for lists, which otherwise use that.
+	pub const fn list_width(mut self, width: usize) -> Style {
+		self.widths[Kind::List as usize] = Some(width);
+		self
+	}
+
+	/// Like [`max_width`](Style::max_width), but only for maps and sets, which otherwise use that.
+	pub const fn map_width(mut self, width: usize) -> Style {
+		self.widths[Kind::Map as usize] = Some(width);
+		self
+	}
+
+	/// The number of fields or elements that a value put on a single line may have, unlimited by
+	/// default.
+	///
+	/// Values with more fields than this are printed over several lines, even if they would fit
+	/// in [`max_width`](Style::max_width), and the other way around: a value is only put on a single
+	/// line if it passes both limits.
+	pub const fn max_fields(mut self, fields: usize) -> Style {
+		self.max_fields = fields;
+		self
+	}
+
+	/// The number of levels of tuples, structs and so on that a value put on a single line may
+	/// have, unlimited by default.
+	///
+	/// For example, `Some(Some(1))` has two levels. Only the brackets in the output count, so
+	/// references and types that print their contents as they are, like `Box`, do not add a level.
+	/// With a limit, the innermost values are put on a single line while the outer ones are
+	/// printed over several lines.
+	pub const fn max_depth(mut self, depth: usize) -> Style {
+		self.max_depth = depth;
+		self
+	}
+
+	/// Which values may be put on a single line, [`CollapseMode::All`] by default.
+	pub const fn mode(mut self, mode: CollapseMode) -> Style {
+		self.mode = mode;
+		self
+	}
+
+	/// Which structs may be put on a single line, [`StructCollapse::All`] by default.
+	pub const fn structs(mut self, structs: StructCollapse) -> Style {
+		self.structs = structs;
+		self
+	}
+
+	/// What to indent the fields of values printed over several lines with, four spaces by
+	/// default as in `std`.
+	///
+	/// ```
+	/// # use compact_debug::{Compact, Style};
+	/// let text = format!("{:#?}", Compact::with([[1]], Style::new().indent("  ")));
+	/// assert_eq!(text, "[\n  [\n    1,\n  ],\n]");
+	/// ```
+	///
+	/// The lines that a `Debug` implementation prints by itself are only indented as a whole, as
+	/// their own indentation cannot be told apart from the rest of the text on them.
+	pub const fn indent(mut self, indent: &'static str) -> Style {
+		self.indent = indent;
+		self
+	}
+
+	/// Whether to put a space after the commas between the fields or elements of values put on a
+	/// single line, as in `A(1, 2)`, which is the default as in `std`. Without it, they are
+	/// printed as `A(1,2)` and `B { x: 1,y: 2 }`, for output meant to be read by programs.
+	///
+	/// Only the commas that this puts in are affected, not those in values that are printed on a
+	/// single line already, which includes everything under `{:?}`.
+	pub const fn comma_space(mut self, on: bool) -> Style {
+		self.comma_space = on;
+		self
+	}
+}
+
+/// The kinds of groups in the output, which can have different widths.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+	Tuple,
+	Struct,
+	List,
+	Map,
+}
+
+impl Kind {
+	/// Tells what kind of group starts with `head`, which ends with an opening bracket.
+	fn of(head: &str) -> Kind {
+		match head.as_bytes()[head.len() - 1] {
+			b'(' => Kind::Tuple,
+			b'[' => Kind::List,
+			_ if is_struct(head) => Kind::Struct,
+			_ => Kind::Map,
+		}
+	}
+}
+
+/// Which values [`Style::mode`] allows to be put on a single line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum CollapseMode {
+	/// Any value, as long as it passes the other limits.
+	#[default]
+	All,
+	/// Only values that contain no other tuples, structs and so on, such as `Address(30016)` or
+	/// `Op(Ne)`, so that the values around them show the shape of the whole.
+	LeavesOnly,
+}
+
+/// Which structs [`Style::structs`] allows to be put on a single line, on top of the other
+/// limits. Unlike [`Style::max_fields`], this does not affect tuples.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum StructCollapse {
+	/// Any struct.
+	#[default]
+	All,
+	/// Only structs with a single field, like `Wrapper { inner: 1 }`, while those with more fields
+	/// are printed over several lines.
+	SingleFieldOnly,
+	/// Only structs inside at least this many other groups, so that `BelowDepth(1)` keeps the
+	/// outermost struct over several lines to show its shape. Tuples, lists and maps are counted
+	/// the same as structs, whether or not they are put on a single line.
+	///
+	/// ```
+	/// # use compact_debug::{Compact, Style, StructCollapse};
+	/// #[derive(Debug)]
+	/// struct Point {
+	///     x: i32,
+	///     y: i32,
+	/// }
+	/// #[derive(Debug)]
+	/// struct Line {
+	///     from: Point,
+	///     to: Point,
+	/// }
+	/// let line = Line { from: Point { x: 0, y: 0 }, to: Point { x: 1, y: 2 } };
+	/// let style = Style::new().structs(StructCollapse::BelowDepth(1));
+	/// assert_eq!(
+	///     format!("{:#?}", Compact::with(&line, style)),
+	///     "Line {\n    from: Point { x: 0, y: 0 },\n    to: Point { x: 1, y: 2 },\n}",
+	/// );
+	/// ```
+	BelowDepth(usize),
+}
+
+impl Style {
+	fn width(&self, kind: Kind) -> usize {
+		let width = self.widths[kind as usize].unwrap_or(self.max_width);
+		width.min(self.max_line)
+	}
+}
+
+impl Default for Style {
+	fn default() -> Style {
+		Style::new()
+	}
+}
+
+/// A value printed with a custom [`Style`], returned by [`Compact::with`].
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct CompactWith<T> {
+	pub value: T,
+	pub style: Style,
+}
+
+impl<T: fmt::Debug> fmt::Debug for CompactWith<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if !f.alternate() {
+			return self.value.fmt(f);
+		}
+		let text = format!("{:#?}", self.value);
+		let mut lines = text.split('\n').peekable();
+		match Node::parse(&mut lines, 0) {
+			Some(node) if lines.peek().is_none() => {
+				let mut out = String::with_capacity(text.len());
+				node.render(&self.style, 0, 0, &mut out);
+				#[cfg(feature = "metrics")]
+				crate::metrics::flush();
+				f.write_str(&out)
+			}
+			_ => f.write_str(&text),
+		}
+	}
+}
+
+/// A value in the `{:#?}` output: either a single line, or a line ending with an opening bracket,
+/// the indented lines of each field, and a line starting with the closing bracket.
+enum Node<'a> {
+	/// A value printed without a builder, usually on a single line, but if a `Debug`
+	/// implementation prints a newline, the value goes on in `more`.
+	Leaf { line: &'a str, more: Vec<&'a str> },
+	Group {
+		head: &'a str,
+		fields: Vec<Node<'a>>,
+		/// Whether the fields are followed by `..`, from `finish_non_exhaustive`.
+		rest: bool,
+		/// The rest of the closing line, starting with the bracket.
+		tail: &'a str,
+	},
+}
+
+type Lines<'a> = std::iter::Peekable<std::str::Split<'a, char>>;
+
+impl<'a> Node<'a> {
+	/// Parses the value starting at the next line, whose lines are indented by `indent` spaces.
+	fn parse(lines: &mut Lines<'a>, indent: usize) -> Option<Self> {
+		let line = lines.next()?.get(indent..)?;
+		if !line.ends_with(['(', '[', '{']) {
+			let mut more = Vec::new();
+			// A field that does not end with a comma is not finished yet
+			if indent != 0 {
+				while !more.last().unwrap_or(&line).ends_with(',') {
+					more.push(lines.next()?.get(indent..)?);
+				}
+			}
+			return Some(Node::Leaf { line, more });
+		}
+		let close = match line.as_bytes()[line.len() - 1] {
+			b'(' => ')',
+			b'[' => ']',
+			_ => '}',
+		};
+		let mut fields = Vec::new();
+		let is_rest = |line: &&str| line.get(indent..) == Some("    ..");
+		while lines.peek()?.get(indent..)?.starts_with("    ") && !is_rest(lines.peek()?) {
+			let mut field = Node::parse(lines, indent + 4)?;
+			let last = match &mut field {
+				Node::Leaf { line, more } => more.last_mut().unwrap_or(line),
+				Node::Group { tail, .. } => tail,
+			};
+			*last = last.strip_suffix(',')?;
+			fields.push(field);
+		}
+		let rest = lines.next_if(is_rest).is_some();
+		let tail = lines.next()?.get(indent..)?;
+		if !tail.starts_with(close) || tail.ends_with(['(', '[', '{']) {
+			return None;
+		}
+		Some(Node::Group {
+			head: line,
+			fields,
+			rest,
+			tail,
+		})
+	}
+
+	/// Prints the value, with the lines after the first indented `indent` levels, where `level` is
+	/// the number of groups around it.
+	fn render(&self, style: &Style, indent: usize, level: usize, out: &mut String) {
+		match *self {
+			Node::Leaf { line, ref more } => {
+				out.push_str(line);
+				for line in more {
+					out.push('\n');
+					push_indent(out, style, indent);
+					out.push_str(line);
+				}
+			}
+			Node::Group {
+				head,
+				ref fields,
+				rest,
+				tail,
+			} => {
+				let line = out.rfind('\n').map_or(0, |i| i + 1);
+				let column = out[line..].chars().count();
+				let kind = Kind::of(head);
+				let collapsed = separators(kind, fields.len(), level, style)
+					.filter(|_| {
+						self.depth() <= style.max_depth
+							&& (style.mode == CollapseMode::All || self.depth() <= 1)
+							// The lines after a newline would be in the middle of the others
+							&& !fields.iter().any(|field| {
+								matches!(field, Node::Leaf { more, .. } if !more.is_empty())
+							})
+					})
+					.and_then(|(sep, pad)| {
+						let width = style.width(kind);
+						if self
+							.flat_width(style, level)
+							.is_some_and(|flat| column + flat > width)
+						{
+							return None;
+						}
+						// Something inside may still be printed over several lines, so try it
+						// out to see if all of the lines fit
+						let mut trial = out[line..].to_owned();
+						#[cfg(feature = "metrics")]
+						let saved = crate::metrics::save();
+						self.render_collapsed(sep, pad, style, indent, level, &mut trial);
+						let fits = trial.split('\n').all(|l| l.chars().count() <= width);
+						// What is inside will be printed again, and counted then
+						#[cfg(feature = "metrics")]
+						if !fits {
+							crate::metrics::restore(saved);
+						}
+						fits.then(|| trial[out.len() - line..].to_owned())
+					});
+				#[cfg(feature = "metrics")]
+				crate::metrics::count(collapsed.is_some());
+				if let Some(text) = collapsed {
+					out.push_str(&text);
+					return;
+				}
+				out.push_str(head);
+				for field in fields {
+					out.push('\n');
+					push_indent(out, style, indent + 1);
+					field.render(style, indent + 1, level + 1, out);
+					out.push(',');
+				}
+				if rest {
+					out.push('\n');
+					push_indent(out, style, indent + 1);
+					out.push_str("..");
+				}
+				out.push('\n');
+				push_indent(out, style, indent);
+				out.push_str(tail);
+			}
+		}
+	}
+
+	/// Prints a group on a single line, except for whatever inside it is not.
+	fn render_collapsed(
+		&self,
+		sep: &str,
+		pad: &str,
+		style: &Style,
+		indent: usize,
+		level: usize,
+		out: &mut String,
+	) {
+		let Node::Group {
+			head,
+			fields,
+			rest,
+			tail,
+		} = self
+		else {
+			unreachable!()
+		};
+		// Nothing to pad, as in `B {}`
+		let pad = if fields.is_empty() && !rest { "" } else { pad };
+		out.push_str(head);
+		out.push_str(pad);
+		for (i, field) in fields.iter().enumerate() {
+			if i != 0 {
+				out.push_str(sep);
+			}
+			field.render(style, indent, level + 1, out);
+		}
+		if *rest {
+			if !fields.is_empty() {
+				out.push_str(sep);
+			}
+			out.push_str("..");
+		}
+		out.push_str(pad);
+		out.push_str(tail);
+	}
+
+	/// The number of groups nested inside each other in the value, including itself.
+	fn depth(&self) -> usize {
+		match self {
+			Node::Leaf { .. } => 0,
+			Node::Group { fields, .. } => 1 + fields.iter().map(Node::depth).max().unwrap_or(0),
+		}
+	}
+
+	/// The width of the value when printed on a single line, or `None` if it contains anything
+	/// that is not put on a single line in this style.
+	fn flat_width(&self, style: &Style, level: usize) -> Option<usize> {
+		match self {
+			Node::Leaf { line, more } => more.is_empty().then(|| line.chars().count()),
+			Node::Group {
+				head,
+				fields,
+				rest,
+				tail,
+			} => {
+				let (sep, pad) = separators(Kind::of(head), fields.len(), level, style)?;
+				let items = fields.len() + usize::from(*rest);
+				let mut width = head.chars().count() + tail.chars().count();
+				width += 2 * pad.len() * usize::from(items != 0);
+				width += sep.len() * items.saturating_sub(1) + 2 * usize::from(*rest);
+				for field in fields {
+					width += field.flat_width(style, level + 1)?;
+				}
+				Some(width)
+			}
+		}
+	}
+}
+
+/// The separator between fields and the padding inside the brackets, if a group of this kind is
+/// put on a single line in this style, regardless of its width.
+fn separators(
+	kind: Kind,
+	fields: usize,
+	level: usize,
+	style: &Style,
+) -> Option<(&'static str, &'static str)> {
+	if fields > style.max_fields {
+		return None;
+	}
+	let sep = if style.comma_space { ", " } else { "," };
+	match kind {
+		Kind::Tuple => Some((sep, "")),
+		Kind::Struct if style.structs == StructCollapse::SingleFieldOnly && fields != 1 => None,
+		Kind::Struct if matches!(style.structs, StructCollapse::BelowDepth(depth) if level < depth) => {
+			None
+		}
+		Kind::Struct => Some((sep, " ")),
+		Kind::List if style.lists => Some((sep, "")),
+		Kind::Map if style.maps => Some((sep, "")),
+		_ => None,
+	}
+}
+
+fn push_indent(out: &mut String, style: &Style, levels: usize) {
+	for _ in 0..levels {
+		out.push_str(style.indent);
+	}
+}
+
+/// Whether a line ending with `{` starts a struct, rather than a map or set, which have no name.
+fn is_struct(head: &str) -> bool {
+	head.strip_suffix(" {")
+		.and_then(|name| name.chars().next_back())
+		.is_some_and(|c| c.is_alphanumeric() || c == '_')
+}
@@ -0,0 +1,54 @@
+#![cfg(all(feature = "cache", feature = "tuples"))]
+
+use std::process::Command;
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct A(u32, u32);
+
+/// Runs this test again as a child process with the cache in `dir`, so that it searches from
+/// scratch.
+fn run(dir: &std::path::Path) {
+	let output = Command::new(std::env::current_exe().unwrap())
+		.args(["--exact", "cache", "--test-threads=1"])
+		.env("COMPACT_DEBUG_CACHE_CHILD", "1")
+		.env("COMPACT_DEBUG_CACHE_DIR", dir)
+		.output()
+		.unwrap();
+	assert!(output.status.success(), "{output:?}");
+}
+
+/// The offsets should be written by the first run, and still be found by later runs, even if
+/// the file holds the wrong ones.
+#[test]
+fn cache() {
+	if std::env::var_os("COMPACT_DEBUG_CACHE_CHILD").is_some() {
+		unsafe { compact_debug::enable(true) };
+		assert_eq!(format!("{:#?}", A(8, 32)), "A(8, 32)");
+		return;
+	}
+	let dir = std::env::temp_dir().join(format!("compact-debug-cache-{}", std::process::id()));
+	let _ = std::fs::remove_dir_all(&dir);
+	run(&dir);
+	let files = std::fs::read_dir(&dir)
+		.unwrap()
+		.map(|entry| entry.unwrap().path())
+		.collect::<Vec<_>>();
+	let [file] = &files[..] else {
+		panic!("expected a single cache file: {files:?}");
+	};
+	let text = std::fs::read_to_string(file).unwrap();
+	assert!(text.contains(" std::fmt::DebugTuple::field 0x"), "{text}");
+
+	run(&dir);
+	assert_eq!(std::fs::read_to_string(file).unwrap(), text);
+
+	let wrong = text
+		.lines()
+		.map(|line| format!("{} 0x1\n", line.split(" 0x").next().unwrap()))
+		.collect::<String>();
+	std::fs::write(file, wrong).unwrap();
+	run(&dir);
+	assert_eq!(std::fs::read_to_string(file).unwrap(), text);
+	std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,42 @@
+#![cfg(feature = "tuples")]
+
+use compact_debug::{
+	compact, compact_alternate, compact_plain, is_enabled, snapshot, write_compact,
+};
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct A(u32, u32);
+
+/// The functions that format a single value with the patch enabled, from any thread.
+#[test]
+fn compact_fns() {
+	#[cfg(feature = "auto")]
+	unsafe {
+		compact_debug::enable(false)
+	};
+	let a = A(8, 32);
+	assert_eq!(unsafe { compact(&a) }, "A(8, 32)");
+	assert_eq!(unsafe { compact("a") }, "\"a\"");
+	let nested = (&a, [1]);
+	assert_eq!(
+		unsafe { compact_alternate(&nested) },
+		"(A(8, 32), [\n    1,\n])"
+	);
+	assert_eq!(compact_plain(&nested), "(A(8, 32), [1])");
+	let mut bytes = Vec::new();
+	unsafe { write_compact(&mut bytes, &nested) }.unwrap();
+	assert_eq!(bytes, b"(A(8, 32), [\n    1,\n])");
+	assert_eq!(unsafe { snapshot(&nested) }, "(A(8, 32), [\n    1,\n])");
+
+	std::thread::scope(|s| {
+		for _ in 0..4 {
+			s.spawn(|| {
+				for _ in 0..100 {
+					assert_eq!(unsafe { compact(&a) }, "A(8, 32)");
+				}
+			});
+		}
+	});
+	assert!(!is_enabled());
+}
@@ -0,0 +1,18 @@
+#![cfg(feature = "tuples")]
+
+use compact_debug::{enable, is_enabled, verify_patch};
+
+/// Threads enabling and disabling the patch at once should take turns.
+#[test]
+fn concurrent() {
+	std::thread::scope(|scope| {
+		for i in 0..4 {
+			scope.spawn(move || {
+				for _ in 0..50 {
+					unsafe { enable(i % 2 == 0) };
+				}
+			});
+		}
+	});
+	assert_eq!(verify_patch(), is_enabled());
+}
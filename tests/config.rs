@@ -0,0 +1,39 @@
+#![cfg(feature = "tuples")]
+
+use compact_debug::Config;
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct A(u32, u32);
+
+#[allow(dead_code)]
+#[derive(Debug)]
+struct B {
+	x: u32,
+	y: u32,
+}
+
+/// Only the selected builders are enabled, and the others are disabled.
+#[test]
+fn config() {
+	#[cfg(feature = "auto")]
+	unsafe {
+		compact_debug::enable(false)
+	};
+	let a = A(8, 32);
+	unsafe { Config::new().tuples(true).apply() }.unwrap();
+	assert_eq!(format!("{a:#?}"), "A(8, 32)");
+	unsafe { Config::new().apply() }.unwrap();
+	assert_eq!(format!("{a:#?}"), "A(\n    8,\n    32,\n)");
+
+	#[cfg(feature = "structs")]
+	{
+		let b = B { x: 8, y: 32 };
+		unsafe { Config::new().structs(true).apply() }.unwrap();
+		assert_eq!(format!("{a:#?}"), "A(\n    8,\n    32,\n)");
+		assert_eq!(format!("{b:#?}"), "B { x: 8, y: 32 }");
+		unsafe { Config::new().tuples(true).apply() }.unwrap();
+		assert_eq!(format!("{a:#?}"), "A(8, 32)");
+		assert_eq!(format!("{b:#?}"), "B {\n    x: 8,\n    y: 32,\n}");
+	}
+}
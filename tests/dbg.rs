@@ -0,0 +1,29 @@
+#![cfg(feature = "tuples")]
+
+use std::process::Command;
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct A(u32, u32);
+
+/// `dbg!` prints to stderr, which the test harness does not capture, so this runs itself again as
+/// a child process and reads its stderr.
+#[test]
+fn dbg() {
+	if std::env::var_os("COMPACT_DEBUG_DBG_CHILD").is_some() {
+		unsafe { compact_debug::enable(true) };
+		dbg!(A(8, 32));
+		return;
+	}
+	let output = Command::new(std::env::current_exe().unwrap())
+		.args(["--exact", "dbg", "--nocapture", "--test-threads=1"])
+		.env("COMPACT_DEBUG_DBG_CHILD", "1")
+		.output()
+		.unwrap();
+	assert!(output.status.success());
+	let stderr = String::from_utf8(output.stderr).unwrap();
+	assert!(
+		stderr.contains("] A(8, 32) = A(8, 32)\n"),
+		"unexpected output: {stderr:?}"
+	);
+}
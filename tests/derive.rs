@@ -0,0 +1,97 @@
+#![cfg(feature = "macros")]
+// The fields are only read by the derived impls, which the lint ignores
+#![allow(dead_code)]
+
+use compact_debug::CompactDebug;
+
+#[derive(CompactDebug)]
+struct Tuple(u32, Vec<u32>);
+
+#[derive(CompactDebug)]
+struct Nested(Tuple, #[doc(hidden)] Unit);
+
+#[derive(CompactDebug)]
+struct Unit;
+
+#[derive(CompactDebug)]
+struct Empty();
+
+#[derive(CompactDebug)]
+pub struct Named<'a, T: Copy, const N: usize = 1>
+where
+	T: PartialEq,
+{
+	pub r#type: &'a [T; N],
+	f: Tuple,
+}
+
+#[derive(CompactDebug)]
+enum Enum<T> {
+	Unit,
+	Tuple(T, T),
+	Named { x: T },
+	Empty {},
+}
+
+#[derive(CompactDebug)]
+enum Never {}
+
+#[test]
+fn derive() {
+	let tuple = Tuple(8, vec![32]);
+	assert_eq!(format!("{tuple:?}"), "Tuple(8, [32])");
+	assert_eq!(format!("{tuple:#?}"), "Tuple(8, [\n    32,\n])");
+	assert_eq!(
+		format!("{:#?}", Nested(Tuple(1, vec![]), Unit)),
+		"Nested(Tuple(1, []), Unit)"
+	);
+	assert_eq!(format!("{:#?}", Empty()), "Empty");
+	assert_eq!(
+		format!(
+			"{:#?}",
+			Named {
+				r#type: &[1],
+				f: Tuple(2, vec![])
+			}
+		),
+		"Named {\n    type: [\n        1,\n    ],\n    f: Tuple(2, []),\n}"
+	);
+	let values = [
+		Enum::Unit,
+		Enum::Tuple(1, 2),
+		Enum::Named { x: 3 },
+		Enum::Empty {},
+	];
+	assert_eq!(
+		format!("{values:#?}"),
+		"[\n    Unit,\n    Tuple(1, 2),\n    Named {\n        x: 3,\n    },\n    Empty,\n]"
+	);
+	assert_eq!(format!("{:08.3?}", Tuple(1, vec![])), "Tuple(00000001, [])");
+	let _ = |never: Never| format!("{never:?}");
+}
+
+/// The same output as `#[derive(Debug)]` with the patch enabled, as long as the fields also
+/// derive it.
+#[cfg(feature = "tuples")]
+#[test]
+fn matches_patch() {
+	#[derive(Debug)]
+	struct Tuple(u32, Vec<u32>);
+	#[derive(Debug)]
+	enum Enum {
+		Tuple(Tuple, (), [u32; 1]),
+	}
+	#[derive(CompactDebug)]
+	struct CompactTuple(u32, Vec<u32>);
+	#[derive(CompactDebug)]
+	enum CompactEnum {
+		Tuple(CompactTuple, (), [u32; 1]),
+	}
+
+	let patched = unsafe { compact_debug::compact(&Enum::Tuple(Tuple(8, vec![32]), (), [1])) };
+	let derived = format!(
+		"{:#?}",
+		CompactEnum::Tuple(CompactTuple(8, vec![32]), (), [1])
+	);
+	assert_eq!(derived.replace("Compact", ""), patched);
+}
@@ -0,0 +1,47 @@
+#![cfg(feature = "tuples")]
+
+use compact_debug::enable;
+
+/// The example at the top of the crate docs, where the list stays spread over several lines while
+/// the tuples in it do not, since only the patch for tuples is enabled.
+#[test]
+fn doc_example() {
+	#[allow(dead_code)]
+	#[derive(Debug)]
+	enum Insn {
+		Goto(Address),
+		Label(Address),
+		Expr(Expr<Expr<Vec<Term>>>, Address),
+	}
+	#[allow(dead_code)]
+	#[derive(Debug)]
+	struct Address(u32);
+	#[derive(Debug)]
+	struct Expr<T>(T);
+	#[allow(dead_code)]
+	#[derive(Debug)]
+	enum Term {
+		Var(u32),
+		Const(u32),
+		Op(Op),
+	}
+	#[derive(Debug)]
+	enum Op {
+		Ne,
+	}
+	let insns = [
+		Insn::Goto(Address(30016)),
+		Insn::Label(Address(29990)),
+		Insn::Expr(
+			Expr(Expr(vec![Term::Var(0), Term::Const(0), Term::Op(Op::Ne)])),
+			Address(30016),
+		),
+	];
+	unsafe { enable(true) };
+	let output = format!("{insns:#?}");
+	assert_eq!(
+		output,
+		"[\n    Goto(Address(30016)),\n    Label(Address(29990)),\n    Expr(Expr(Expr([\n        Var(0),\n        \
+		Const(0),\n        Op(Ne),\n    ])), Address(30016)),\n]"
+	);
+}
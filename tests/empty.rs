@@ -0,0 +1,23 @@
+#![cfg(feature = "tuples")]
+
+use std::fmt;
+
+use compact_debug::enable;
+
+/// Tuples without fields never reach the patched code, but should not be affected either.
+#[test]
+fn empty() {
+	#[derive(Debug)]
+	struct Empty();
+	struct Manual;
+	impl fmt::Debug for Manual {
+		fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+			f.debug_tuple("Manual").finish()
+		}
+	}
+	unsafe { enable(true) };
+	assert_eq!(format!("{:#?}", Empty()), "Empty");
+	assert_eq!(format!("{:#?}", Manual), "Manual");
+	assert_eq!(format!("{:#?}", ()), "()");
+	assert_eq!(format!("{:#?}", Some(())), "Some(())");
+}
@@ -0,0 +1,38 @@
+#![cfg(feature = "tuples")]
+
+use compact_debug::enable;
+
+#[allow(dead_code)]
+#[derive(Debug)]
+enum E {
+	Unit,
+	One(u32),
+	Two(u32, u32),
+	Named { x: u32, y: u32 },
+	Empty {},
+}
+
+/// Tuple variants are printed like tuple structs, and the other variants are left alone.
+#[test]
+fn enums() {
+	unsafe { enable(true) };
+	assert_eq!(format!("{:#?}", E::Unit), "Unit");
+	assert_eq!(format!("{:#?}", E::One(8)), "One(8)");
+	assert_eq!(format!("{:#?}", E::Two(8, 32)), "Two(8, 32)");
+	assert_eq!(
+		format!("{:#?}", E::Named { x: 8, y: 32 }),
+		"Named {\n    x: 8,\n    y: 32,\n}"
+	);
+	assert_eq!(format!("{:#?}", E::Empty {}), "Empty");
+	assert_eq!(format!("{:#?}", Some(E::One(8))), "Some(One(8))");
+
+	#[cfg(feature = "structs")]
+	{
+		unsafe { compact_debug::enable_structs(true) };
+		assert_eq!(
+			format!("{:#?}", E::Named { x: 8, y: 32 }),
+			"Named { x: 8, y: 32 }"
+		);
+		assert_eq!(format!("{:#?}", E::Empty {}), "Empty");
+	}
+}
@@ -0,0 +1,25 @@
+#![cfg(feature = "tuples")]
+
+use compact_debug::{enable, enable_from_env, is_enabled, CompactDebugError};
+
+/// `COMPACT_DEBUG` enables the patch if `1` and disables it if `0` or unset, and anything else is
+/// an error that leaves it alone.
+#[test]
+fn env() {
+	unsafe { enable(false) };
+	std::env::set_var("COMPACT_DEBUG", "1");
+	unsafe { enable_from_env() }.unwrap();
+	assert!(is_enabled());
+	std::env::set_var("COMPACT_DEBUG", "yes");
+	assert!(matches!(
+		unsafe { enable_from_env() },
+		Err(CompactDebugError::InvalidEnv(_))
+	));
+	assert!(is_enabled());
+	std::env::set_var("COMPACT_DEBUG", "0");
+	unsafe { enable_from_env() }.unwrap();
+	assert!(!is_enabled());
+	std::env::remove_var("COMPACT_DEBUG");
+	unsafe { enable_from_env() }.unwrap();
+	assert!(!is_enabled());
+}
@@ -0,0 +1,20 @@
+// With `auto`, the functions have been searched before the test starts
+#![cfg(all(feature = "tuples", not(feature = "auto")))]
+
+use compact_debug::{skipped_functions, CompactDebugError, Config};
+
+/// The exclusions apply to the first search, and cannot be changed after it.
+#[test]
+fn exclude() {
+	let config = Config::new()
+		.tuples(true)
+		.exclude(&["debug_tuple_fields_finish"]);
+	unsafe { config.apply() }.unwrap();
+	assert!(skipped_functions().contains(&"std::fmt::Formatter::debug_tuple_fields_finish"));
+	unsafe { config.apply() }.unwrap();
+	unsafe { Config::new().apply() }.unwrap();
+	assert!(matches!(
+		unsafe { Config::new().exclude(&[]).apply() },
+		Err(CompactDebugError::ExcludedAfterSearch)
+	));
+}
@@ -0,0 +1,33 @@
+#![cfg(feature = "tuples")]
+
+use std::fmt;
+
+use compact_debug::{enable, enable_functions};
+
+/// Patching the builder's functions by hand should work the same as `enable`, without the helpers.
+#[test]
+fn functions() {
+	use std::fmt::DebugTuple;
+	unsafe { enable(false) };
+	struct Manual;
+	impl fmt::Debug for Manual {
+		fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+			f.debug_tuple("A").field(&8).field(&32).finish()
+		}
+	}
+	let functions: [(&'static str, *const u8); 2] = [
+		(
+			"std::fmt::DebugTuple::field",
+			DebugTuple::field as *const u8,
+		),
+		(
+			"std::fmt::DebugTuple::finish",
+			DebugTuple::finish as *const u8,
+		),
+	];
+	unsafe { enable_functions(&functions, true) }.unwrap();
+	let patched = format!("{Manual:#?}");
+	unsafe { enable_functions(&functions, false) }.unwrap();
+	assert_eq!(patched, "A(8, 32)");
+	assert_eq!(format!("{Manual:#?}"), "A(\n    8,\n    32,\n)");
+}
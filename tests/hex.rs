@@ -0,0 +1,17 @@
+#![cfg(feature = "tuples")]
+
+use compact_debug::enable;
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct A(u32, u32);
+
+/// `#` also adds the `0x` prefix for hex, which the patch should leave alone.
+#[test]
+fn hex() {
+	unsafe { enable(true) };
+	let hex = A(255, 16);
+	assert_eq!(format!("{hex:#x?}"), "A(0xff, 0x10)");
+	assert_eq!(format!("{hex:#X?}"), "A(0xFF, 0x10)");
+	assert_eq!(format!("{hex:x?}"), "A(ff, 10)");
+}
@@ -0,0 +1,48 @@
+#![cfg(all(feature = "hook", compact_debug_fmt_helpers))]
+
+use compact_debug::{
+	enable_for_current_thread, hook, set_filter, try_enable, unhook, CompactDebugError,
+};
+
+/// Deciding for each tuple as it is printed, and then only on one thread.
+#[test]
+fn hook_tuples() {
+	#[allow(dead_code)]
+	#[derive(Debug)]
+	struct A(u32, u32);
+	let a = Some(A(1, 2));
+
+	unsafe { hook(|_, fields| fields.len() == 1) }.unwrap();
+	let hooked = format!("{a:#?}");
+	if cfg!(target_arch = "x86_64") {
+		assert!(matches!(
+			unsafe { try_enable(true) },
+			Err(CompactDebugError::Hooked)
+		));
+	}
+	unsafe { unhook() }.unwrap();
+	if cfg!(target_arch = "x86_64") {
+		assert_eq!(hooked, "Some(A(\n    1,\n    2,\n))");
+	}
+	assert_eq!(
+		format!("{a:#?}"),
+		"Some(\n    A(\n        1,\n        2,\n    ),\n)"
+	);
+
+	unsafe { enable_for_current_thread(true) }.unwrap();
+	let here = format!("{a:#?}");
+	let there = std::thread::scope(|scope| scope.spawn(|| format!("{a:#?}")).join().unwrap());
+	set_filter(|name| name != "Some");
+	let filtered = format!("{a:#?}");
+	unsafe { unhook() }.unwrap();
+	// Neither the flag nor the filter outlive `unhook`
+	unsafe { hook(|_, _| false) }.unwrap();
+	let rehooked = format!("{a:#?}");
+	unsafe { unhook() }.unwrap();
+	if cfg!(target_arch = "x86_64") {
+		assert_eq!(here, "Some(A(1, 2))");
+		assert_eq!(filtered, "Some(\n    A(1, 2),\n)");
+	}
+	assert_eq!(there, "Some(\n    A(\n        1,\n        2,\n    ),\n)");
+	assert_eq!(rehooked, there);
+}
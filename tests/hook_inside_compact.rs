@@ -0,0 +1,14 @@
+#![cfg(all(feature = "hook", feature = "tuples", compact_debug_fmt_helpers))]
+
+use compact_debug::{hook, is_enabled, with_compact, CompactDebugError};
+
+/// Hooking inside `with_compact` is an error, and leaves the patch as it was.
+#[test]
+fn hook_inside_compact() {
+	let was_enabled = is_enabled();
+	let result = unsafe { with_compact(|| hook(|_, _| true)) };
+	if cfg!(all(target_arch = "x86_64", not(miri))) {
+		assert!(matches!(result, Err(CompactDebugError::InsideCompact)));
+	}
+	assert_eq!(is_enabled(), was_enabled);
+}
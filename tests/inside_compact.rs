@@ -0,0 +1,15 @@
+#![cfg(feature = "tuples")]
+
+use compact_debug::{is_enabled, try_enable, with_compact, CompactDebugError};
+
+/// Changing the patch inside `with_compact` is an error rather than a panic or a deadlock.
+#[test]
+fn inside_compact() {
+	#[cfg(feature = "auto")]
+	unsafe {
+		compact_debug::enable(false)
+	};
+	let result = unsafe { with_compact(|| try_enable(false)) };
+	assert!(matches!(result, Err(CompactDebugError::InsideCompact)));
+	assert!(!is_enabled());
+}
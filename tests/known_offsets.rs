@@ -0,0 +1,14 @@
+#![cfg(feature = "tuples")]
+
+use compact_debug::find_sites;
+
+/// Checking the sites at the known offsets should give the same as scanning.
+#[test]
+fn known_offsets() {
+	// Except on x86, the scan does not recognize the sites once they are patched
+	#[cfg(feature = "auto")]
+	unsafe {
+		compact_debug::enable(false)
+	};
+	assert_eq!(find_sites(true).unwrap(), find_sites(false).unwrap());
+}
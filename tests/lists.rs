@@ -0,0 +1,32 @@
+#![cfg(all(feature = "tuples", feature = "lists"))]
+
+use compact_debug::{enable, enable_lists, snapshot};
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct A(u32, u32);
+
+/// Lists are enabled separately from the tuples in them, and `snapshot` leaves them alone.
+#[test]
+fn lists() {
+	#[cfg(feature = "auto")]
+	unsafe {
+		compact_debug::enable(false)
+	};
+	let list = vec![A(8, 32), A(1, 2)];
+	unsafe { enable_lists(true) };
+	assert_eq!(
+		format!("{list:#?}"),
+		"[A(\n    8,\n    32,\n), A(\n    1,\n    2,\n)]"
+	);
+	assert_eq!(format!("{:#?}", Vec::<u32>::new()), "[]");
+	unsafe { enable(true) };
+	assert_eq!(format!("{list:#?}"), "[A(8, 32), A(1, 2)]");
+	let snapshot = unsafe { snapshot(&list) };
+	assert_eq!(snapshot, "[\n    A(8, 32),\n    A(1, 2),\n]");
+	assert_eq!(format!("{list:#?}"), "[A(8, 32), A(1, 2)]");
+	unsafe { enable_lists(false) };
+	assert_eq!(format!("{list:#?}"), "[\n    A(8, 32),\n    A(1, 2),\n]");
+	assert_eq!(format!("{:#?}", A(8, 32)), "A(8, 32)");
+	assert_eq!(format!("{:#?}", (vec![1, 2],)), "([\n    1,\n    2,\n],)");
+}
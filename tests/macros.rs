@@ -0,0 +1,36 @@
+#![cfg(all(feature = "macros", feature = "tuples"))]
+
+// These run at the same time, so each would see the patch disabled halfway through if the
+// guards did not nest
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct A(u32, u32);
+
+#[compact_debug::test]
+fn first() {
+	for _ in 0..1000 {
+		assert_eq!(format!("{:#?}", A(8, 32)), "A(8, 32)");
+	}
+}
+
+#[compact_debug::test]
+fn second() {
+	for _ in 0..1000 {
+		assert_eq!(format!("{:#?}", Some(A(8, 32))), "Some(A(8, 32))");
+	}
+}
+
+#[compact_debug::test]
+#[should_panic]
+fn failing() {
+	panic!();
+}
+
+// The message is formatted before the guard is dropped
+#[compact_debug::test]
+#[should_panic(expected = "left: Some(A(8, 32))")]
+fn message() {
+	let left = Some(A(8, 32));
+	assert!(left.is_none(), "left: {left:#?}");
+}
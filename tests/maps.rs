@@ -0,0 +1,48 @@
+#![cfg(all(feature = "tuples", feature = "maps"))]
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use compact_debug::{enable, enable_maps};
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct A(u32, u32);
+
+/// Each entry of a map goes on the same line, whether written with `entry` or not.
+#[test]
+fn maps() {
+	#[cfg(feature = "auto")]
+	unsafe {
+		compact_debug::enable(false)
+	};
+	let map = BTreeMap::from([(1, A(8, 32)), (2, A(1, 2))]);
+	unsafe { enable_maps(true) };
+	assert_eq!(
+		format!("{map:#?}"),
+		"{1: A(\n    8,\n    32,\n), 2: A(\n    1,\n    2,\n)}"
+	);
+	unsafe { enable(true) };
+	assert_eq!(format!("{map:#?}"), "{1: A(8, 32), 2: A(1, 2)}");
+	let nested = BTreeMap::from([("a", Some(A(1, 2)))]);
+	assert_eq!(format!("{nested:#?}"), "{\"a\": Some(A(1, 2))}");
+	// The key and value written separately, rather than with `entry`
+	struct Split;
+	impl fmt::Debug for Split {
+		fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+			f.debug_map()
+				.key(&"a")
+				.value(&Some(A(1, 2)))
+				.key(&(1, 2))
+				.value(&())
+				.finish()
+		}
+	}
+	assert_eq!(format!("{Split:#?}"), "{\"a\": Some(A(1, 2)), (1, 2): ()}");
+	assert_eq!(format!("{:#?}", BTreeMap::<u32, u32>::new()), "{}");
+	unsafe { enable_maps(false) };
+	assert_eq!(
+		format!("{map:#?}"),
+		"{\n    1: A(8, 32),\n    2: A(1, 2),\n}"
+	);
+}
@@ -0,0 +1,26 @@
+#![cfg(feature = "metrics")]
+
+use compact_debug::{collapse_stats, Compact};
+
+/// Each group that `Compact` looks at is counted once, as either collapsed or expanded.
+#[test]
+fn counted() {
+	// Not tuples, which the patch may already have put on a single line with the `auto` feature
+	#[allow(dead_code)]
+	#[derive(Debug)]
+	struct O {
+		p: P,
+	}
+	#[allow(dead_code)]
+	#[derive(Debug)]
+	struct P {
+		x: u32,
+	}
+	let before = collapse_stats();
+	let text = format!("{:#?}", Compact::with_width(O { p: P { x: 1 } }, 17));
+	let after = collapse_stats();
+	assert_eq!(text, "O {\n    p: P { x: 1 },\n}");
+	assert_eq!(after.seen - before.seen, 2);
+	assert_eq!(after.collapsed - before.collapsed, 1);
+	assert_eq!(after.expanded - before.expanded, 1);
+}
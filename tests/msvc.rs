@@ -0,0 +1,15 @@
+#![cfg(all(windows, target_env = "msvc", feature = "tuples"))]
+
+use compact_debug::enable;
+
+/// On MSVC, the patch is written under the W^X protection of Windows, and the instruction cache
+/// is flushed after it.
+#[test]
+fn msvc() {
+	#[derive(Debug)]
+	struct A(u32, u32);
+	unsafe { enable(true) };
+	assert_eq!(format!("{:#?}", A(1, 2)), "A(1, 2)");
+	unsafe { enable(false) };
+	assert_eq!(format!("{:#?}", A(1, 2)), "A(\n    1,\n    2,\n)");
+}
@@ -0,0 +1,31 @@
+#![cfg(feature = "tuples")]
+
+use std::fmt;
+
+use compact_debug::enable;
+
+struct Unfinished(&'static [u32]);
+
+impl fmt::Debug for Unfinished {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let mut tuple = f.debug_tuple("A");
+		for field in self.0 {
+			tuple.field(field);
+		}
+		tuple.finish_non_exhaustive()
+	}
+}
+
+/// The `..` of a non-exhaustive tuple goes on the same line as its fields.
+#[test]
+fn non_exhaustive() {
+	unsafe { enable(true) };
+	assert_eq!(format!("{:#?}", Unfinished(&[1, 2])), "A(1, 2, ..)");
+	assert_eq!(format!("{:#?}", Unfinished(&[])), "A(..)");
+	assert_eq!(format!("{:#?}", [Unfinished(&[1])]), "[\n    A(1, ..),\n]");
+	unsafe { enable(false) };
+	assert_eq!(
+		format!("{:#?}", Unfinished(&[1, 2])),
+		"A(\n    1,\n    2,\n    ..\n)"
+	);
+}
@@ -0,0 +1,14 @@
+#![cfg(feature = "tuples")]
+
+use compact_debug::{enable, enable_once, is_enabled, verify_patch};
+
+/// Only the first call should do anything, after which the patch is left to `enable`.
+#[test]
+fn once() {
+	unsafe { enable(false) };
+	unsafe { enable_once() }.unwrap();
+	assert!(is_enabled() && verify_patch());
+	unsafe { enable(false) };
+	unsafe { enable_once() }.unwrap();
+	assert!(!is_enabled());
+}
@@ -0,0 +1,52 @@
+#![cfg(feature = "tuples")]
+
+use compact_debug::{enable, is_enabled, verify_patch};
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct A(u32, u32);
+
+#[allow(dead_code)]
+#[derive(Debug)]
+struct B {
+	x: u32,
+	y: u32,
+}
+
+/// Tuples are put on a single line under `{:#?}` while the patch is enabled, and nothing else is.
+#[test]
+fn patch() {
+	#[cfg(feature = "auto")]
+	{
+		let env = std::env::var_os("COMPACT_DEBUG");
+		assert_eq!(is_enabled(), env.is_none_or(|value| value == "1"));
+		unsafe { enable(false) };
+	}
+	let a = A(8, 32);
+	let b = B { x: 8, y: 32 };
+
+	assert_eq!(format!("{a:?}"), "A(8, 32)");
+	assert_eq!(format!("{a:#?}"), "A(\n    8,\n    32,\n)");
+	assert_eq!(format!("{b:?}"), "B { x: 8, y: 32 }");
+	assert_eq!(format!("{b:#?}"), "B {\n    x: 8,\n    y: 32,\n}");
+
+	assert!(!is_enabled());
+	unsafe { enable(true) };
+	unsafe { enable(true) };
+	assert!(is_enabled());
+	assert!(verify_patch());
+
+	assert_eq!(format!("{a:?}"), "A(8, 32)");
+	assert_eq!(format!("{a:#?}"), "A(8, 32)");
+	assert_eq!(format!("{b:?}"), "B { x: 8, y: 32 }");
+	assert_eq!(format!("{b:#?}"), "B {\n    x: 8,\n    y: 32,\n}");
+
+	unsafe { enable(false) };
+	assert!(!is_enabled());
+	assert!(!verify_patch());
+
+	assert_eq!(format!("{a:?}"), "A(8, 32)");
+	assert_eq!(format!("{a:#?}"), "A(\n    8,\n    32,\n)");
+	assert_eq!(format!("{b:?}"), "B { x: 8, y: 32 }");
+	assert_eq!(format!("{b:#?}"), "B {\n    x: 8,\n    y: 32,\n}");
+}
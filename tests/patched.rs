@@ -0,0 +1,32 @@
+#![cfg(feature = "tuples")]
+
+use compact_debug::{
+	byte_snapshot, enable, enable_patched, is_enabled, restore_bytes, verify_patch,
+};
+
+/// Dropping the handle should put back the bytes from before, even after the patch was changed.
+#[test]
+fn patched() {
+	unsafe { enable(false) };
+	let patched = unsafe { enable_patched() }.unwrap();
+	assert!(is_enabled() && verify_patch());
+	unsafe { enable(false) };
+	unsafe { enable(true) };
+	drop(patched);
+	assert!(!is_enabled() && !verify_patch());
+
+	unsafe { enable(true) };
+	let patched = unsafe { enable_patched() }.unwrap();
+	unsafe { enable(false) };
+	drop(patched);
+	assert!(is_enabled() && verify_patch());
+
+	let on = byte_snapshot().unwrap();
+	unsafe { enable(false) };
+	let off = byte_snapshot().unwrap();
+	assert_ne!(on, off);
+	unsafe { restore_bytes(&on) }.unwrap();
+	assert!(is_enabled() && verify_patch());
+	unsafe { restore_bytes(&off) }.unwrap();
+	assert!(!is_enabled() && !verify_patch());
+}
@@ -0,0 +1,32 @@
+#![cfg(feature = "tuples")]
+
+use compact_debug::{enable, is_enabled, patched_sites};
+use region::Protection;
+
+/// The pages with the sites should be left with the protection they had, and in particular not
+/// writable.
+#[test]
+fn protection() {
+	let was_enabled = is_enabled();
+	// All of each page that is made writable. The regions themselves may be split up once the
+	// pages have been written to, so only their protection is compared
+	let query = || {
+		patched_sites()
+			.iter()
+			.flat_map(|&site| {
+				let page = region::page::floor(site as *const u8);
+				region::query_range(page, region::page::size()).unwrap()
+			})
+			.map(|region| region.unwrap().protection())
+			.collect::<Vec<_>>()
+	};
+	let before = query();
+	assert!(!before.is_empty());
+	unsafe { enable(!was_enabled) };
+	assert_eq!(query(), before);
+	unsafe { enable(was_enabled) };
+	assert_eq!(query(), before);
+	assert!(before
+		.iter()
+		.all(|protection| !protection.contains(Protection::WRITE)));
+}
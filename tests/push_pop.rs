@@ -0,0 +1,23 @@
+#![cfg(feature = "tuples")]
+
+use compact_debug::{pop, push};
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct A(u32, u32);
+
+/// The patch stays enabled until every `push` has been matched by a `pop`.
+#[test]
+fn push_pop() {
+	#[cfg(feature = "auto")]
+	unsafe {
+		compact_debug::enable(false)
+	};
+	let a = A(8, 32);
+	unsafe { push() };
+	unsafe { push() };
+	unsafe { pop() };
+	assert_eq!(format!("{a:#?}"), "A(8, 32)");
+	unsafe { pop() };
+	assert_eq!(format!("{a:#?}"), "A(\n    8,\n    32,\n)");
+}
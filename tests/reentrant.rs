@@ -0,0 +1,11 @@
+#![cfg(feature = "tuples")]
+
+use compact_debug::{enable, is_enabled, try_enable_while_writing};
+
+/// Changing the patch from inside the write, as a panic hook might, should be skipped.
+#[test]
+fn reentrant() {
+	unsafe { enable(false) };
+	assert!(unsafe { try_enable_while_writing(true) }.is_ok());
+	assert!(!is_enabled());
+}
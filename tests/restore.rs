@@ -0,0 +1,17 @@
+#![cfg(feature = "tuples")]
+
+use compact_debug::{enable, is_enabled, restore_original};
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct A(u32, u32);
+
+/// Restoring the original code disables the patch, and can be done again.
+#[test]
+fn restore() {
+	unsafe { enable(true) };
+	unsafe { restore_original() };
+	unsafe { restore_original() };
+	assert!(!is_enabled());
+	assert_eq!(format!("{:#?}", A(8, 32)), "A(\n    8,\n    32,\n)");
+}
@@ -0,0 +1,34 @@
+#![cfg(feature = "tuples")]
+
+use compact_debug::{enable, is_enabled, scoped};
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct A(u32, u32);
+
+/// The guard puts back whatever was there before it, including when nested.
+#[test]
+fn guards() {
+	#[cfg(feature = "auto")]
+	unsafe {
+		compact_debug::enable(false)
+	};
+	let a = A(8, 32);
+	{
+		let _guard = unsafe { scoped() };
+		let _inner = unsafe { scoped() };
+		assert_eq!(format!("{a:#?}"), "A(8, 32)");
+	}
+	assert!(!is_enabled());
+	unsafe { enable(true) };
+	drop(unsafe { scoped() });
+	assert!(is_enabled());
+	unsafe { enable(false) };
+	assert_eq!(
+		unsafe {
+			scoped! { format!("{a:#?}") }
+		},
+		"A(8, 32)"
+	);
+	assert!(!is_enabled());
+}
@@ -0,0 +1,16 @@
+#![cfg(feature = "sets")]
+
+use std::collections::BTreeSet;
+
+use compact_debug::enable_sets;
+
+/// Sets are put on a single line like tuples, including empty ones.
+#[test]
+fn sets() {
+	let set = BTreeSet::from([1, 2]);
+	unsafe { enable_sets(true) };
+	assert_eq!(format!("{set:#?}"), "{1, 2}");
+	assert_eq!(format!("{:#?}", BTreeSet::<u32>::new()), "{}");
+	unsafe { enable_sets(false) };
+	assert_eq!(format!("{set:#?}"), "{\n    1,\n    2,\n}");
+}
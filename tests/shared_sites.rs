@@ -0,0 +1,15 @@
+#![cfg(all(feature = "lists", feature = "sets"))]
+
+use compact_debug::{enable_lists, enable_sets};
+
+/// The linker may merge the functions of lists and sets, and disabling one should not disable
+/// the other.
+#[test]
+fn shared_sites() {
+	unsafe { enable_lists(true) };
+	unsafe { enable_sets(true) };
+	unsafe { enable_sets(false) };
+	assert_eq!(format!("{:#?}", [1, 2]), "[1, 2]");
+	unsafe { enable_lists(false) };
+	assert_eq!(format!("{:#?}", [1, 2]), "[\n    1,\n    2,\n]");
+}
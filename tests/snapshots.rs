@@ -0,0 +1,109 @@
+#![cfg(feature = "tuples")]
+
+//! Formats a set of values with and without the patch, and compares the output with the `.snap`
+//! files in `tests/snapshots`. Run with `COMPACT_DEBUG_BLESS=1` to write the files instead.
+//!
+//! Everything runs in the single test below, so nothing else in this binary touches the patch
+//! while it does.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::path::Path;
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct Address(u32);
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct Point {
+	x: i32,
+	y: i32,
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+enum Insn {
+	Goto(Address),
+	Label(Address),
+	Expr(Vec<Expr>, Address),
+	Move { from: Point, to: Point },
+	Nop,
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+enum Expr {
+	Var(u32),
+	Const(i64),
+	Op(&'static str),
+}
+
+fn cases() -> Vec<(&'static str, Box<dyn Debug>)> {
+	vec![
+		("tuple", Box::new(Address(30016))),
+		("unit_tuple", Box::new(())),
+		("tuples", Box::new((1, ("a", 2.5), (Address(1),)))),
+		("struct", Box::new(Point { x: 1, y: -2 })),
+		(
+			"enum",
+			Box::new([
+				Insn::Goto(Address(30016)),
+				Insn::Label(Address(29990)),
+				Insn::Expr(
+					vec![Expr::Var(0), Expr::Const(0), Expr::Op("Ne")],
+					Address(30016),
+				),
+				Insn::Move {
+					from: Point { x: 0, y: 0 },
+					to: Point { x: 3, y: 4 },
+				},
+				Insn::Nop,
+			]),
+		),
+		("option", Box::new([Some(Some(5)), Some(None), None])),
+		("result", Box::new([Ok(Address(1)), Err("no")])),
+		("vec", Box::new(vec![vec![1, 2], vec![], vec![3]])),
+		(
+			"map",
+			Box::new(BTreeMap::from([
+				("a", Some(Address(1))),
+				("b", None),
+				("c", Some(Address(3))),
+			])),
+		),
+	]
+}
+
+fn render(value: &dyn Debug) -> String {
+	unsafe { compact_debug::enable(false) };
+	let plain = format!("{value:#?}");
+	unsafe { compact_debug::enable(true) };
+	let compact = format!("{value:#?}");
+	unsafe { compact_debug::enable(false) };
+	format!("--- disabled ---\n{plain}\n--- enabled ---\n{compact}\n")
+}
+
+#[test]
+fn snapshots() {
+	let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots");
+	let bless = std::env::var_os("COMPACT_DEBUG_BLESS").is_some();
+	let mut failed = Vec::new();
+	for (name, value) in cases() {
+		let path = dir.join(format!("{name}.snap"));
+		let actual = render(&*value);
+		if bless {
+			std::fs::write(&path, &actual).unwrap();
+			continue;
+		}
+		let expected = std::fs::read_to_string(&path).unwrap_or_default();
+		if actual != expected {
+			eprintln!("{name}: expected\n{expected}\nbut got\n{actual}");
+			failed.push(name);
+		}
+	}
+	assert!(
+		failed.is_empty(),
+		"snapshots differ: {failed:?}; run with COMPACT_DEBUG_BLESS=1 to update them"
+	);
+}
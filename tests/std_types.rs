@@ -0,0 +1,43 @@
+#![cfg(feature = "tuples")]
+
+use compact_debug::enable;
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct A(u32, u32);
+
+/// The types from `std` that are most commonly printed.
+#[test]
+fn std_types() {
+	#[cfg(feature = "auto")]
+	unsafe {
+		compact_debug::enable(false)
+	};
+	let some = Some(Some(5));
+	let ok = Ok::<_, ()>(A(1, 2));
+	let list = vec![A(1, 2), A(3, 4)];
+	let mixed = Some((A(1, 2), vec![Some(3)]));
+	assert_eq!(
+		format!("{some:#?}"),
+		"Some(\n    Some(\n        5,\n    ),\n)"
+	);
+	assert_eq!(
+		format!("{ok:#?}"),
+		"Ok(\n    A(\n        1,\n        2,\n    ),\n)"
+	);
+	assert_eq!(
+		format!("{list:#?}"),
+		"[\n    A(\n        1,\n        2,\n    ),\n    A(\n        3,\n        4,\n    ),\n]"
+	);
+	assert_eq!(
+		format!("{mixed:#?}"),
+		"Some(\n    (\n        A(\n            1,\n            2,\n        ),\n        [\n            \
+		Some(\n                3,\n            ),\n        ],\n    ),\n)"
+	);
+	unsafe { enable(true) };
+	assert_eq!(format!("{some:#?}"), "Some(Some(5))");
+	assert_eq!(format!("{:#?}", Some(Some(Some(5)))), "Some(Some(Some(5)))");
+	assert_eq!(format!("{ok:#?}"), "Ok(A(1, 2))");
+	assert_eq!(format!("{list:#?}"), "[\n    A(1, 2),\n    A(3, 4),\n]");
+	assert_eq!(format!("{mixed:#?}"), "Some((A(1, 2), [\n    Some(3),\n]))");
+}
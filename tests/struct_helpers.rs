@@ -0,0 +1,90 @@
+#![cfg(feature = "structs")]
+
+use std::fmt;
+
+use compact_debug::enable_structs;
+
+#[allow(dead_code)]
+#[derive(Debug, Default)]
+struct S1 {
+	a: u8,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Default)]
+struct S3 {
+	a: u8,
+	b: u8,
+	c: u8,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Default)]
+struct S4 {
+	a: u8,
+	b: u8,
+	c: u8,
+	d: u8,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Default)]
+struct S5 {
+	a: u8,
+	b: u8,
+	c: u8,
+	d: u8,
+	e: u8,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Default)]
+struct S6 {
+	a: u8,
+	b: u8,
+	c: u8,
+	d: u8,
+	e: u8,
+	f: u8,
+}
+
+/// Derives use a separate helper for each number of fields up to five, and the generic builder
+/// beyond that, and each of them should be patched.
+#[test]
+fn struct_helpers() {
+	struct Manual;
+	impl fmt::Debug for Manual {
+		fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+			f.debug_struct("Manual")
+				.field("a", &0)
+				.finish_non_exhaustive()
+		}
+	}
+	#[derive(Debug)]
+	struct Empty {}
+	struct Unfinished;
+	impl fmt::Debug for Unfinished {
+		fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+			f.debug_struct("Unfinished").finish_non_exhaustive()
+		}
+	}
+
+	unsafe { enable_structs(true) };
+	assert_eq!(format!("{:#?}", S1::default()), "S1 { a: 0 }");
+	assert_eq!(format!("{:#?}", S3::default()), "S3 { a: 0, b: 0, c: 0 }");
+	assert_eq!(
+		format!("{:#?}", S4::default()),
+		"S4 { a: 0, b: 0, c: 0, d: 0 }"
+	);
+	assert_eq!(
+		format!("{:#?}", S5::default()),
+		"S5 { a: 0, b: 0, c: 0, d: 0, e: 0 }"
+	);
+	assert_eq!(
+		format!("{:#?}", S6::default()),
+		"S6 { a: 0, b: 0, c: 0, d: 0, e: 0, f: 0 }"
+	);
+	assert_eq!(format!("{:#?}", Manual), "Manual { a: 0, .. }");
+	assert_eq!(format!("{:#?}", Empty {}), "Empty");
+	assert_eq!(format!("{:#?}", Unfinished), "Unfinished { .. }");
+}
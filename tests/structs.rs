@@ -0,0 +1,34 @@
+#![cfg(all(feature = "tuples", feature = "structs"))]
+
+use compact_debug::{enable, enable_structs, restore_original};
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct A(u32, u32);
+
+#[allow(dead_code)]
+#[derive(Debug)]
+struct B {
+	x: u32,
+	y: u32,
+}
+
+/// Structs and tuples are enabled separately, and can be enabled together.
+#[test]
+fn structs() {
+	#[cfg(feature = "auto")]
+	unsafe {
+		compact_debug::enable(false)
+	};
+	let a = A(8, 32);
+	let b = B { x: 8, y: 32 };
+	unsafe { enable_structs(true) };
+	assert_eq!(format!("{a:#?}"), "A(\n    8,\n    32,\n)");
+	assert_eq!(format!("{b:#?}"), "B { x: 8, y: 32 }");
+	unsafe { enable(true) };
+	assert_eq!(format!("{:#?}", (&a, &b)), "(A(8, 32), B { x: 8, y: 32 })");
+	unsafe { enable_structs(false) };
+	assert_eq!(format!("{b:#?}"), "B {\n    x: 8,\n    y: 32,\n}");
+	unsafe { restore_original() };
+	assert_eq!(format!("{a:#?}"), "A(\n    8,\n    32,\n)");
+}
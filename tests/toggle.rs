@@ -0,0 +1,23 @@
+#![cfg(feature = "tuples")]
+
+use compact_debug::{disable, is_enabled, toggle};
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct A(u32, u32);
+
+/// Each call flips the patch, whatever it was.
+#[test]
+fn toggles() {
+	#[cfg(feature = "auto")]
+	unsafe {
+		compact_debug::enable(false)
+	};
+	unsafe { toggle() };
+	assert!(is_enabled());
+	unsafe { toggle() };
+	assert!(!is_enabled());
+	unsafe { toggle() };
+	unsafe { disable() };
+	assert_eq!(format!("{:#?}", A(8, 32)), "A(\n    8,\n    32,\n)");
+}
@@ -0,0 +1,17 @@
+#![cfg(feature = "tuples")]
+
+use compact_debug::{byte_snapshot, panic_while_writing, verify_patch};
+
+/// A panic while writing the sites should leave none of them written.
+#[test]
+fn unwind() {
+	#[cfg(feature = "auto")]
+	unsafe {
+		compact_debug::enable(false)
+	};
+	let before = byte_snapshot().unwrap();
+	let result = std::panic::catch_unwind(|| unsafe { panic_while_writing() });
+	assert!(result.is_err());
+	assert_eq!(byte_snapshot().unwrap(), before);
+	assert!(!verify_patch());
+}
@@ -0,0 +1,29 @@
+#![cfg(feature = "tuples")]
+
+use compact_debug::{enable, is_enabled, with_compact, without_compact};
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct A(u32, u32);
+
+/// The patch is only changed for the closure, and nesting them is fine.
+#[test]
+fn closures() {
+	#[cfg(feature = "auto")]
+	unsafe {
+		compact_debug::enable(false)
+	};
+	let a = A(8, 32);
+	let text = unsafe {
+		with_compact(|| {
+			with_compact(|| {});
+			format!("{a:#?}")
+		})
+	};
+	assert_eq!(text, "A(8, 32)");
+	assert!(!is_enabled());
+	unsafe { enable(true) };
+	let text = unsafe { without_compact(|| format!("{a:#?}")) };
+	assert_eq!(text, "A(\n    8,\n    32,\n)");
+	assert!(is_enabled());
+}
@@ -0,0 +1,355 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use compact_debug::{CollapseMode, Compact, CompactDebug, StructCollapse, Style};
+
+#[allow(dead_code)]
+#[derive(Debug)]
+struct A(u32, u32);
+
+#[allow(dead_code)]
+#[derive(Debug)]
+struct B {
+	x: u32,
+	y: Vec<A>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+struct Wrapper {
+	inner: A,
+}
+
+fn b() -> B {
+	B {
+		x: 8,
+		y: vec![A(8, 32)],
+	}
+}
+
+fn wide() -> (A, (A, A)) {
+	(A(1, 2), (A(3, 4), A(5, 6)))
+}
+
+fn map() -> BTreeMap<u32, &'static str> {
+	BTreeMap::from([(1, "{"), (2, "}")])
+}
+
+/// The output is compared with the patch disabled, which the `auto` feature enables.
+fn unpatched() {
+	#[cfg(feature = "auto")]
+	unsafe {
+		compact_debug::enable(false)
+	};
+}
+
+#[test]
+fn single_line() {
+	unpatched();
+	let b = b();
+	assert_eq!(format!("{:?}", Compact(&b)), format!("{b:?}"));
+	assert_eq!(
+		format!("{:#?}", Compact(Some(Some(Some(5))))),
+		"Some(Some(Some(5)))"
+	);
+	assert_eq!(
+		format!("{:#?}", Compact(&b)),
+		"B { x: 8, y: [\n    A(8, 32),\n] }"
+	);
+	assert_eq!(format!("{:#?}", Compact(A(8, 32))), "A(8, 32)");
+	assert_eq!(format!("{:#?}", Compact(())), "()");
+}
+
+/// Groups without fields, whether written by a builder or by hand.
+#[test]
+fn empty() {
+	unpatched();
+	#[allow(dead_code)]
+	#[derive(Debug)]
+	enum Empty {
+		Variant {},
+	}
+	struct Skipped;
+	impl fmt::Debug for Skipped {
+		fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+			f.debug_struct("Skipped").finish()
+		}
+	}
+	struct Spread;
+	impl fmt::Debug for Spread {
+		fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+			f.write_str("Spread {\n}")
+		}
+	}
+	assert_eq!(
+		format!("{:#?}", Compact((Empty::Variant {}, Skipped, Spread))),
+		"(Variant, Skipped, Spread {})"
+	);
+}
+
+#[test]
+fn non_exhaustive() {
+	unpatched();
+	struct Unfinished(bool);
+	impl fmt::Debug for Unfinished {
+		fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+			if self.0 {
+				f.debug_tuple("A")
+					.field(&1)
+					.field(&2)
+					.finish_non_exhaustive()
+			} else {
+				f.debug_struct("B").field("x", &1).finish_non_exhaustive()
+			}
+		}
+	}
+	let unfinished = [Unfinished(true), Unfinished(false)];
+	assert_eq!(
+		format!("{:#?}", Compact(&unfinished)),
+		"[\n    A(1, 2, ..),\n    B { x: 1, .. },\n]"
+	);
+	assert_eq!(
+		format!("{:#?}", Compact::with_width(&unfinished, 10)),
+		"[\n    A(\n        1,\n        2,\n        ..\n    ),\n    B {\n        x: 1,\n        ..\n    },\n]"
+	);
+}
+
+/// Compact values inside others, which have already been collapsed as far as they go.
+#[test]
+fn nested() {
+	unpatched();
+	let nested = (A(1, 2), vec![Compact(A(3, 4))]);
+	assert_eq!(
+		format!("{:#?}", Compact(&nested)),
+		"(A(1, 2), [\n    A(3, 4),\n])"
+	);
+	assert_eq!(
+		format!("{:#?}", Compact::with(&nested, Style::new().lists(true))),
+		"(A(1, 2), [A(3, 4)])"
+	);
+	let values: Vec<Box<dyn CompactDebug>> = vec![Box::new(A(1, 2)), Box::new(b()), Box::new(())];
+	assert_eq!(
+		format!("{values:#?}"),
+		"[\n    A(1, 2),\n    B { x: 8, y: [\n        A(8, 32),\n    ] },\n    (),\n]"
+	);
+}
+
+#[test]
+fn width() {
+	unpatched();
+	let wide = wide();
+	assert_eq!(
+		format!("{:#?}", Compact(&wide)),
+		"(A(1, 2), (A(3, 4), A(5, 6)))"
+	);
+	assert_eq!(
+		format!("{:#?}", Compact::with_width(&wide, 24)),
+		"(\n    A(1, 2),\n    (A(3, 4), A(5, 6)),\n)"
+	);
+	assert_eq!(
+		format!("{:#?}", Compact::with_width(&wide, 16)),
+		"(\n    A(1, 2),\n    (\n        A(3, 4),\n        A(5, 6),\n    ),\n)"
+	);
+	let style = Style::new().max_width(usize::MAX).max_line(16);
+	assert_eq!(
+		format!("{:#?}", Compact::with(&wide, style)),
+		format!("{:#?}", Compact::with_width(&wide, 16))
+	);
+	assert_eq!(
+		format!("{:#?}", Compact::with_depth(Box::new(&wide), 2)),
+		"(\n    A(1, 2),\n    (A(3, 4), A(5, 6)),\n)"
+	);
+	assert_eq!(
+		format!(
+			"{:#?}",
+			Compact::with(&wide, Style::new().max_width(16).indent("  "))
+		),
+		"(\n  A(1, 2),\n  (\n    A(3, 4),\n    A(5, 6),\n  ),\n)"
+	);
+}
+
+#[test]
+fn max_fields() {
+	unpatched();
+	assert_eq!(
+		format!("{:#?}", Compact::with(wide(), Style::new().max_fields(1))),
+		"(\n    A(\n        1,\n        2,\n    ),\n    (\n        A(\n            3,\n            4,\n        ),\n        A(\n            5,\n            6,\n        ),\n    ),\n)"
+	);
+	assert_eq!(
+		format!(
+			"{:#?}",
+			Compact::with(Some(A(1, 2)), Style::new().max_fields(1))
+		),
+		"Some(A(\n    1,\n    2,\n))"
+	);
+}
+
+#[test]
+fn leaves_only() {
+	unpatched();
+	assert_eq!(
+		format!(
+			"{:#?}",
+			Compact::with(wide(), Style::new().mode(CollapseMode::LeavesOnly))
+		),
+		"(\n    A(1, 2),\n    (\n        A(3, 4),\n        A(5, 6),\n    ),\n)"
+	);
+}
+
+#[test]
+fn structs() {
+	unpatched();
+	let style = Style::new().structs(StructCollapse::SingleFieldOnly);
+	assert_eq!(
+		format!("{:#?}", Compact::with(Some(b()), style)),
+		"Some(B {\n    x: 8,\n    y: [\n        A(8, 32),\n    ],\n})"
+	);
+	assert_eq!(
+		format!(
+			"{:#?}",
+			Compact::with(Some(Wrapper { inner: A(1, 2) }), style)
+		),
+		"Some(Wrapper { inner: A(1, 2) })"
+	);
+	// Every kind of group counts towards the depth, whether a list that stays over several lines
+	// or a tuple that does not
+	let style = Style::new().structs(StructCollapse::BelowDepth(1));
+	assert_eq!(
+		format!("{:#?}", Compact::with(Wrapper { inner: A(1, 2) }, style)),
+		"Wrapper {\n    inner: A(1, 2),\n}"
+	);
+	assert_eq!(
+		format!("{:#?}", Compact::with([Wrapper { inner: A(1, 2) }], style)),
+		"[\n    Wrapper { inner: A(1, 2) },\n]"
+	);
+	let style = Style::new().structs(StructCollapse::BelowDepth(2));
+	assert_eq!(
+		format!(
+			"{:#?}",
+			Compact::with(Some(Wrapper { inner: A(1, 2) }), style)
+		),
+		"Some(Wrapper {\n    inner: A(1, 2),\n})"
+	);
+	assert_eq!(
+		format!(
+			"{:#?}",
+			Compact::with(Some(Some(Wrapper { inner: A(1, 2) })), style)
+		),
+		"Some(Some(Wrapper { inner: A(1, 2) }))"
+	);
+}
+
+#[test]
+fn maps() {
+	unpatched();
+	let map = map();
+	assert_eq!(
+		format!("{:#?}", Compact([(&map, 1)])),
+		"[\n    ({\n        1: \"{\",\n        2: \"}\",\n    }, 1),\n]"
+	);
+	let style = Style::new().maps(true).map_width(16).tuple_width(16);
+	assert_eq!(
+		format!("{:#?}", Compact::with(&map, style)),
+		"{1: \"{\", 2: \"}\"}"
+	);
+	assert_eq!(
+		format!("{:#?}", Compact::with([&map], style)),
+		"[\n    {\n        1: \"{\",\n        2: \"}\",\n    },\n]"
+	);
+	assert_eq!(
+		format!("{:#?}", Compact::with(wide(), style)),
+		"(\n    A(1, 2),\n    (\n        A(3, 4),\n        A(5, 6),\n    ),\n)"
+	);
+}
+
+/// The example from the README, with lists and without the space after commas.
+#[test]
+fn lists() {
+	unpatched();
+	#[allow(dead_code)]
+	#[derive(Debug)]
+	struct Address(u32);
+	#[derive(Debug)]
+	struct Expr<T>(T);
+	#[derive(Debug)]
+	enum Op {
+		Ne,
+	}
+	#[allow(dead_code)]
+	#[derive(Debug)]
+	enum Term {
+		Var(u32),
+		Const(u32),
+		Op(Op),
+	}
+	#[allow(dead_code)]
+	#[derive(Debug)]
+	enum Insn {
+		Goto(Address),
+		Label(Address),
+		Expr(Expr<Expr<Vec<Term>>>, Address),
+	}
+	let code = vec![
+		Insn::Goto(Address(30016)),
+		Insn::Label(Address(29990)),
+		Insn::Expr(
+			Expr(Expr(vec![Term::Var(0), Term::Const(0), Term::Op(Op::Ne)])),
+			Address(30016),
+		),
+	];
+	assert_eq!(
+		format!("{:#?}", Compact::with_width(&code[2], 19)),
+		"Expr(\n    Expr(Expr([\n        Var(0),\n        Const(0),\n        Op(Ne),\n    ])),\n    Address(30016),\n)"
+	);
+	let style = Style::new().lists(true);
+	assert_eq!(
+		format!("{:#?}", Compact::with(&code, style)),
+		"[\n    Goto(Address(30016)),\n    Label(Address(29990)),\n    \
+		Expr(Expr(Expr([Var(0), Const(0), Op(Ne)])), Address(30016)),\n]"
+	);
+	assert_eq!(
+		format!("{:#?}", Compact::with(&code, style.comma_space(false))),
+		"[\n    Goto(Address(30016)),\n    Label(Address(29990)),\n    \
+		Expr(Expr(Expr([Var(0),Const(0),Op(Ne)])),Address(30016)),\n]"
+	);
+	assert_eq!(
+		format!("{:#?}", Compact::with(b(), Style::new().comma_space(false))),
+		"B { x: 8,y: [\n    A(8,32),\n] }"
+	);
+	assert_eq!(
+		format!("{:#?}", Compact::with(&code, style.max_width(40))),
+		"[\n    Goto(Address(30016)),\n    Label(Address(29990)),\n    Expr(\n        Expr(\n            \
+		Expr(\n                [\n                    Var(0),\n                    Const(0),\n                    \
+		Op(Ne),\n                ],\n            ),\n        ),\n        Address(30016),\n    ),\n]"
+	);
+}
+
+#[test]
+fn newlines() {
+	unpatched();
+	struct Raw;
+	impl fmt::Debug for Raw {
+		fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+			f.write_str("two\nlines")
+		}
+	}
+	#[allow(dead_code)]
+	#[derive(Debug)]
+	struct Text {
+		text: String,
+		raw: Raw,
+	}
+	let text = Text {
+		text: "two\nlines".to_owned(),
+		raw: Raw,
+	};
+	// Strings escape their newlines, so only the other one keeps the struct expanded
+	assert_eq!(
+		format!("{:#?}", Compact((1, &text.text))),
+		"(1, \"two\\nlines\")"
+	);
+	assert_eq!(
+		format!("{:#?}", Compact((1, &text))),
+		"(1, Text {\n    text: \"two\\nlines\",\n    raw: two\n    lines,\n})"
+	);
+}